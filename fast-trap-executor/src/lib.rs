@@ -0,0 +1,243 @@
+//! 基于快速陷入的抢占式异步执行器。
+//!
+//! 在根控制流上轮询异步任务。一次轮询耗尽时间片时被时钟中断抢占，
+//! 挂起为一个线程，执行器换到另一个工作栈上继续轮询其他任务，稍后再恢复被抢占的轮询。
+
+#![no_std]
+#![deny(warnings, missing_docs)]
+
+use core::{
+    cell::{Cell, UnsafeCell},
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, RawWaker, RawWakerVTable, Waker},
+};
+use fast_trap::{preemptible, IllegalStack, Preempted, Preemption, TrapStackBlock};
+
+/// 异步任务。
+pub struct Task<'a> {
+    future: UnsafeCell<Pin<&'a mut dyn Future<Output = ()>>>,
+    woken: AtomicBool,
+    state: Cell<State>,
+}
+
+/// 任务状态。
+///
+/// 没有堆分配，被抢占的现场直接放在任务里。
+#[allow(clippy::large_enum_variant)]
+enum State {
+    /// 等待唤醒后轮询。
+    Pending,
+    /// 轮询被抢占，挂起在一个工作栈上。
+    Preempted(Preempted, usize),
+    /// 已完成。
+    Ready,
+}
+
+impl<'a> Task<'a> {
+    /// 包装一个异步任务。
+    #[inline]
+    pub fn new(future: Pin<&'a mut dyn Future<Output = ()>>) -> Self {
+        Self {
+            future: UnsafeCell::new(future),
+            woken: AtomicBool::new(true),
+            state: Cell::new(State::Pending),
+        }
+    }
+
+    /// 任务已完成。
+    #[inline]
+    pub fn is_ready(&self) -> bool {
+        let state = self.state.replace(State::Ready);
+        let ans = matches!(state, State::Ready);
+        self.state.set(state);
+        ans
+    }
+
+    /// 任务可以继续执行：被抢占或已被唤醒。
+    #[inline]
+    fn is_runnable(&self) -> bool {
+        let state = self.state.replace(State::Ready);
+        let ans = match state {
+            State::Pending => self.woken.load(Ordering::Acquire),
+            State::Preempted(..) => true,
+            State::Ready => false,
+        };
+        self.state.set(state);
+        ans
+    }
+
+    /// 构造唤醒这个任务的 `Waker`。
+    #[inline]
+    fn waker(&self) -> Waker {
+        unsafe { Waker::from_raw(RawWaker::new(&self.woken as *const _ as _, &VTABLE)) }
+    }
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, noop);
+
+unsafe fn clone(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &VTABLE)
+}
+
+unsafe fn wake(data: *const ()) {
+    (*data.cast::<AtomicBool>()).store(true, Ordering::Release)
+}
+
+unsafe fn noop(_: *const ()) {}
+
+/// 抢占式执行器。
+///
+/// 每个工作栈最多承载一次被抢占的轮询，工作栈用尽时不再抢占。
+pub struct Executor<'a, T, const N: usize> {
+    timer: UnsafeCell<T>,
+    tasks: &'a [Task<'a>],
+    stacks: UnsafeCell<&'a mut [[u8; N]]>,
+    /// 空闲工作栈的位图。
+    free: Cell<usize>,
+    /// 当前控制流所在的工作栈。
+    running: Cell<usize>,
+    /// 正在轮询的任务。
+    polling: Cell<Option<usize>>,
+    /// 下一个检查的任务。
+    next: Cell<usize>,
+    /// 调用 `run` 的控制流。
+    root: UnsafeCell<Option<Preempted>>,
+}
+
+impl<'a, T: Preemption, const N: usize> Executor<'a, T, N> {
+    /// 构造执行器。
+    ///
+    /// `timer` 只用于设置和取消定时器，它的 `preempted` 不会被调用。
+    pub fn new(timer: T, tasks: &'a mut [Task<'a>], stacks: &'a mut [[u8; N]]) -> Self {
+        assert!(stacks.len() <= usize::BITS as usize);
+        let free = match stacks.len() {
+            n if n == usize::BITS as usize => !0,
+            n => (1 << n) - 1,
+        };
+        Self {
+            timer: UnsafeCell::new(timer),
+            tasks,
+            stacks: UnsafeCell::new(stacks),
+            free: Cell::new(free),
+            running: Cell::new(0),
+            polling: Cell::new(None),
+            next: Cell::new(0),
+            root: UnsafeCell::new(None),
+        }
+    }
+
+    /// 在 `stack` 上构造陷入栈，以 `budget` 为时间片轮询所有任务直到完成。
+    ///
+    /// 至少需要一个工作栈。
+    pub fn run(&self, stack: impl TrapStackBlock, budget: u64) -> Result<(), IllegalStack> {
+        preemptible(stack, budget, &mut &*self, || unsafe {
+            let worker = self.start_worker().expect("no free worker stack");
+            worker.yield_to(&mut *self.root.get());
+        })
+    }
+
+    /// 在一个空闲的工作栈上构造执行器控制流。
+    fn start_worker(&self) -> Option<Preempted> {
+        let free = self.free.get();
+        let i = free.trailing_zeros() as usize;
+        let stacks = unsafe { &mut *self.stacks.get() };
+        let stack = stacks.get_mut(i)?;
+        self.free.set(free & !(1 << i));
+        self.running.set(i);
+        Some(Preempted::new(worker::<T, N>, self as *const _ as _, stack))
+    }
+
+    /// 循环轮询任务。
+    ///
+    /// 所有任务完成时恢复调用 `run` 的控制流，丢弃当前控制流。
+    fn schedule(&self) -> ! {
+        loop {
+            let len = self.tasks.len();
+            let next = self.next.get();
+            match (next..len)
+                .chain(0..next)
+                .find(|&i| self.tasks[i].is_runnable())
+            {
+                Some(i) => {
+                    self.next.set((i + 1) % len);
+                    self.run_task(i);
+                }
+                None if self.tasks.iter().all(Task::is_ready) => unsafe {
+                    self.release();
+                    (*self.root.get()).take().unwrap().resume()
+                },
+                None => unsafe { core::arch::asm!("wfi") },
+            }
+        }
+    }
+
+    /// 轮询或恢复第 `i` 个任务。
+    fn run_task(&self, i: usize) {
+        let task = &self.tasks[i];
+        match task.state.replace(State::Pending) {
+            State::Pending => {
+                task.woken.store(false, Ordering::Release);
+                let waker = task.waker();
+                let mut cx = Context::from_waker(&waker);
+                self.polling.set(Some(i));
+                let poll = unsafe { &mut *task.future.get() }.as_mut().poll(&mut cx);
+                self.polling.set(None);
+                if poll.is_ready() {
+                    task.state.set(State::Ready);
+                }
+            }
+            State::Preempted(preempted, stack) => unsafe {
+                // 切换完成前不能再被抢占，切换时会重新设置定时器
+                (*self.timer.get()).disarm();
+                self.release();
+                self.running.set(stack);
+                self.polling.set(Some(i));
+                preempted.resume()
+            },
+            State::Ready => unreachable!(),
+        }
+    }
+
+    /// 释放当前工作栈。
+    #[inline]
+    fn release(&self) {
+        self.free.set(self.free.get() | 1 << self.running.get());
+    }
+}
+
+impl<T: Preemption, const N: usize> Preemption for &Executor<'_, T, N> {
+    #[inline]
+    fn arm(&mut self, budget: u64) {
+        unsafe { &mut *self.timer.get() }.arm(budget)
+    }
+
+    #[inline]
+    fn disarm(&mut self) {
+        unsafe { &mut *self.timer.get() }.disarm()
+    }
+
+    /// 挂起被抢占的轮询，在新的工作栈上继续轮询其他任务。
+    fn preempted(&mut self, preempted: Preempted) -> Preempted {
+        let Some(i) = self.polling.get() else {
+            return preempted;
+        };
+        let running = self.running.get();
+        match self.start_worker() {
+            Some(worker) => {
+                self.tasks[i]
+                    .state
+                    .set(State::Preempted(preempted, running));
+                self.polling.set(None);
+                worker
+            }
+            None => preempted,
+        }
+    }
+}
+
+/// 工作栈上的执行器控制流入口。
+extern "C" fn worker<T: Preemption, const N: usize>(executor: usize) -> ! {
+    unsafe { &*(executor as *const Executor<T, N>) }.schedule()
+}
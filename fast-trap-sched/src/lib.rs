@@ -0,0 +1,293 @@
+//! 基于快速陷入的参考调度器。
+//!
+//! 每个任务是一个独立的控制流，时钟中断在完整路径中抢占当前任务，由运行队列决定接下来运行哪个任务。
+//! 没有可运行的任务时运行空闲任务，它用 `wfi` 等待下一次中断。
+//! 调度器为每个任务统计运行时间。
+
+#![no_std]
+#![deny(warnings, missing_docs)]
+
+mod queue;
+
+pub use queue::{FixedPriority, RoundRobin, RunQueue};
+
+use core::cell::{Cell, UnsafeCell};
+use fast_trap::{preemptible, yield_now, IllegalStack, Preempted, Preemption, TrapStackBlock};
+
+/// 时钟。
+pub trait Clock {
+    /// 读取当前时间，单位与时间片相同。
+    fn now(&self) -> u64;
+}
+
+/// 任务。
+///
+/// 任务被换下时，它的控制流上下文保存在这里。
+pub struct Task {
+    priority: usize,
+    state: Cell<State>,
+    flow: Cell<Option<Preempted>>,
+    runtime: Cell<u64>,
+}
+
+/// 任务状态。
+#[derive(Clone, Copy)]
+enum State {
+    /// 尚未创建。
+    Empty,
+    /// 在运行队列中。
+    Ready,
+    /// 正在运行。
+    Running,
+    /// 等待到指定时间。
+    Sleeping(u64),
+    /// 已结束，保存返回值。
+    Exited(usize),
+}
+
+impl Task {
+    /// 构造优先级为 `priority` 的任务槽。
+    ///
+    /// 优先级的含义由运行队列决定。
+    #[inline]
+    pub const fn new(priority: usize) -> Self {
+        Self {
+            priority,
+            state: Cell::new(State::Empty),
+            flow: Cell::new(None),
+            runtime: Cell::new(0),
+        }
+    }
+
+    /// 任务的优先级。
+    #[inline]
+    pub fn priority(&self) -> usize {
+        self.priority
+    }
+
+    /// 任务累计运行的时间。
+    #[inline]
+    pub fn runtime(&self) -> u64 {
+        self.runtime.get()
+    }
+
+    /// 任务结束时的返回值，未结束时返回 `None`。
+    #[inline]
+    pub fn exit_code(&self) -> Option<usize> {
+        match self.state.get() {
+            State::Exited(ret) => Some(ret),
+            _ => None,
+        }
+    }
+}
+
+/// 正在运行的控制流。
+#[derive(Clone, Copy)]
+enum Running {
+    /// 调用 [`Scheduler::run`] 的控制流。
+    Root,
+    /// 空闲任务。
+    Idle,
+    /// 第 `i` 个任务。
+    Task(usize),
+}
+
+/// 抢占式调度器。
+///
+/// `T` 提供定时器和时钟，`Q` 决定任务的运行顺序。
+pub struct Scheduler<'a, T, Q> {
+    timer: UnsafeCell<T>,
+    queue: UnsafeCell<Q>,
+    tasks: &'a [Task],
+    running: Cell<Running>,
+    /// 当前控制流开始运行的时间。
+    started: Cell<u64>,
+    idle: Cell<Option<Preempted>>,
+    idle_time: Cell<u64>,
+    root: UnsafeCell<Option<Preempted>>,
+}
+
+impl<'a, T: Preemption + Clock, Q: RunQueue> Scheduler<'a, T, Q> {
+    /// 构造调度器。
+    ///
+    /// `timer` 只用于设置和取消定时器以及读取时间，它的 `preempted` 和 `exited` 不会被调用。
+    pub fn new(timer: T, queue: Q, tasks: &'a [Task]) -> Self {
+        Self {
+            timer: UnsafeCell::new(timer),
+            queue: UnsafeCell::new(queue),
+            tasks,
+            running: Cell::new(Running::Root),
+            started: Cell::new(0),
+            idle: Cell::new(None),
+            idle_time: Cell::new(0),
+            root: UnsafeCell::new(None),
+        }
+    }
+
+    /// 在 `stack` 上创建第 `id` 个任务执行 `entry(arg)`。
+    ///
+    /// 只能在 [`Scheduler::run`] 之前调用。
+    pub fn spawn(
+        &self,
+        id: usize,
+        stack: &'a mut [u8],
+        entry: extern "C" fn(usize) -> usize,
+        arg: usize,
+    ) {
+        let task = &self.tasks[id];
+        assert!(matches!(task.state.get(), State::Empty | State::Exited(_)));
+        task.flow.set(Some(Preempted::with_exit(entry, arg, stack)));
+        task.runtime.set(0);
+        task.state.set(State::Ready);
+        unsafe { &mut *self.queue.get() }.push(id, task.priority);
+    }
+
+    /// 在 `stack` 上构造陷入栈，以 `budget` 为时间片运行所有任务直到结束。
+    ///
+    /// 空闲任务运行在 `idle_stack` 上。
+    pub fn run(
+        &self,
+        stack: impl TrapStackBlock,
+        budget: u64,
+        idle_stack: &mut [u8],
+    ) -> Result<(), IllegalStack> {
+        self.idle.set(Some(Preempted::new(idle, 0, idle_stack)));
+        self.idle_time.set(0);
+        self.running.set(Running::Root);
+        let ans = preemptible(stack, budget, &mut &*self, || unsafe {
+            // 切换完成前不能再被抢占，切换时会重新设置定时器
+            (*self.timer.get()).disarm();
+            let next = self.switch_next();
+            next.yield_to(&mut *self.root.get());
+        });
+        self.idle.set(None);
+        ans
+    }
+
+    /// 正在运行的任务。
+    #[inline]
+    pub fn current(&self) -> Option<usize> {
+        match self.running.get() {
+            Running::Task(i) => Some(i),
+            Running::Root | Running::Idle => None,
+        }
+    }
+
+    /// 当前任务睡眠 `ticks` 个计时周期。
+    ///
+    /// 只能在任务中调用。
+    pub fn sleep(&self, ticks: u64) {
+        let i = self.current().expect("sleep outside of task");
+        let timer = unsafe { &mut *self.timer.get() };
+        // 修改状态时不能被抢占，让出时会重新设置定时器
+        timer.disarm();
+        self.tasks[i]
+            .state
+            .set(State::Sleeping(timer.now() + ticks));
+        yield_now();
+    }
+
+    /// 空闲任务累计运行的时间。
+    #[inline]
+    pub fn idle_time(&self) -> u64 {
+        self.idle_time.get()
+    }
+
+    /// 为换下的控制流统计运行时间。
+    fn account(&self, now: u64) {
+        let elapsed = now - self.started.get();
+        let runtime = match self.running.get() {
+            Running::Root => return,
+            Running::Idle => &self.idle_time,
+            Running::Task(i) => &self.tasks[i].runtime,
+        };
+        runtime.set(runtime.get() + elapsed);
+    }
+
+    /// 唤醒到时的任务。
+    fn wake_sleepers(&self, now: u64) {
+        let queue = unsafe { &mut *self.queue.get() };
+        for (i, task) in self.tasks.iter().enumerate() {
+            if matches!(task.state.get(), State::Sleeping(t) if t <= now) {
+                task.state.set(State::Ready);
+                queue.push(i, task.priority);
+            }
+        }
+    }
+
+    /// 从运行队列取出下一个任务，没有可运行的任务时取出空闲任务。
+    fn switch_next(&self) -> Preempted {
+        self.started.set(unsafe { &*self.timer.get() }.now());
+        match unsafe { &mut *self.queue.get() }.pop() {
+            Some(i) => {
+                let task = &self.tasks[i];
+                task.state.set(State::Running);
+                self.running.set(Running::Task(i));
+                task.flow.take().unwrap()
+            }
+            None => {
+                self.running.set(Running::Idle);
+                self.idle.take().unwrap()
+            }
+        }
+    }
+}
+
+impl<T: Preemption + Clock, Q: RunQueue> Preemption for &Scheduler<'_, T, Q> {
+    #[inline]
+    fn arm(&mut self, budget: u64) {
+        unsafe { &mut *self.timer.get() }.arm(budget)
+    }
+
+    #[inline]
+    fn disarm(&mut self) {
+        unsafe { &mut *self.timer.get() }.disarm()
+    }
+
+    /// 换下当前控制流，切换到运行队列中的下一个任务。
+    fn preempted(&mut self, preempted: Preempted) -> Preempted {
+        let now = unsafe { &*self.timer.get() }.now();
+        self.account(now);
+        match self.running.get() {
+            Running::Root => return preempted,
+            Running::Idle => self.idle.set(Some(preempted)),
+            Running::Task(i) => {
+                let task = &self.tasks[i];
+                if let State::Running = task.state.get() {
+                    task.state.set(State::Ready);
+                    unsafe { &mut *self.queue.get() }.push(i, task.priority);
+                }
+                task.flow.set(Some(preempted));
+            }
+        }
+        self.wake_sleepers(now);
+        self.switch_next()
+    }
+
+    /// 当前任务结束，切换到下一个任务；所有任务都结束时恢复调用 `run` 的控制流。
+    fn exited(&mut self, ret: usize) -> Preempted {
+        let now = unsafe { &*self.timer.get() }.now();
+        self.account(now);
+        let Running::Task(i) = self.running.get() else {
+            unreachable!()
+        };
+        self.tasks[i].state.set(State::Exited(ret));
+        if self
+            .tasks
+            .iter()
+            .all(|task| matches!(task.state.get(), State::Empty | State::Exited(_)))
+        {
+            self.running.set(Running::Root);
+            return unsafe { &mut *self.root.get() }.take().unwrap();
+        }
+        self.wake_sleepers(now);
+        self.switch_next()
+    }
+}
+
+/// 空闲任务，等待中断。
+extern "C" fn idle(_: usize) -> ! {
+    loop {
+        unsafe { core::arch::asm!("wfi") };
+    }
+}
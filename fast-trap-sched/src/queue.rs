@@ -0,0 +1,78 @@
+/// 运行队列。
+///
+/// 保存就绪任务的序号，决定它们的运行顺序。
+pub trait RunQueue {
+    /// 优先级为 `priority` 的第 `id` 个任务就绪。
+    fn push(&mut self, id: usize, priority: usize);
+
+    /// 取出下一个要运行的任务。
+    fn pop(&mut self) -> Option<usize>;
+}
+
+/// 轮转队列，忽略优先级，先就绪的任务先运行。
+///
+/// 最多容纳 `N` 个任务。
+#[derive(Clone, Copy)]
+pub struct RoundRobin<const N: usize> {
+    ids: [usize; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RoundRobin<N> {
+    /// 构造空的队列。
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            ids: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<const N: usize> RunQueue for RoundRobin<N> {
+    #[inline]
+    fn push(&mut self, id: usize, _priority: usize) {
+        assert!(self.len < N, "run queue full");
+        self.ids[(self.head + self.len) % N] = id;
+        self.len += 1;
+    }
+
+    #[inline]
+    fn pop(&mut self) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        let id = self.ids[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(id)
+    }
+}
+
+/// 固定优先级队列。
+///
+/// 优先级 0 最高，总是先运行优先级最高的就绪任务，同一优先级的任务轮转。
+/// 每个优先级最多容纳 `N` 个任务。
+pub struct FixedPriority<const LEVELS: usize, const N: usize>([RoundRobin<N>; LEVELS]);
+
+impl<const LEVELS: usize, const N: usize> FixedPriority<LEVELS, N> {
+    /// 构造空的队列。
+    #[inline]
+    pub const fn new() -> Self {
+        Self([RoundRobin::new(); LEVELS])
+    }
+}
+
+impl<const LEVELS: usize, const N: usize> RunQueue for FixedPriority<LEVELS, N> {
+    #[inline]
+    fn push(&mut self, id: usize, priority: usize) {
+        self.0[priority].push(id, priority)
+    }
+
+    #[inline]
+    fn pop(&mut self) -> Option<usize> {
+        self.0.iter_mut().find_map(RoundRobin::pop)
+    }
+}
@@ -0,0 +1,231 @@
+use crate::IrqController;
+use core::arch::asm;
+
+// IMSIC 相关的控制状态寄存器，新的名字汇编器不一定认识，直接使用编号。
+
+#[cfg(feature = "riscv-m")]
+macro_rules! imsic_csr {
+    (iselect) => {
+        "0x350"
+    };
+    (ireg) => {
+        "0x351"
+    };
+    (topei) => {
+        "0x35c"
+    };
+}
+#[cfg(feature = "riscv-s")]
+macro_rules! imsic_csr {
+    (iselect) => {
+        "0x150"
+    };
+    (ireg) => {
+        "0x151"
+    };
+    (topei) => {
+        "0x15c"
+    };
+}
+
+/// 当前特权级的 IMSIC 中断文件，通过控制状态寄存器访问。
+///
+/// 外部中断照常进入 `trap_entry`，快速路径通过 [`IrqController::claim`]
+/// 以一次 `topei` 交换认领中断，认领同时清除了挂起位，不需要完成。
+pub struct Imsic;
+
+impl Imsic {
+    const EIDELIVERY: usize = 0x70;
+    const EITHRESHOLD: usize = 0x72;
+    const EIE0: usize = 0xc0;
+
+    /// 打开中断文件的中断投递，只有编号小于 `threshold` 的中断会触发，0 表示不限制。
+    ///
+    /// # Safety
+    ///
+    /// 这个函数操作硬件寄存器，必须关中断调用。
+    #[inline]
+    pub unsafe fn init(threshold: usize) {
+        Self::write(Self::EITHRESHOLD, threshold);
+        Self::write(Self::EIDELIVERY, 1);
+    }
+
+    /// 打开中断 `id`。
+    ///
+    /// # Safety
+    ///
+    /// 这个函数操作硬件寄存器，必须关中断调用。
+    #[inline]
+    pub unsafe fn enable(id: usize) {
+        let (select, bit) = Self::locate(id);
+        Self::select(select);
+        asm!(concat!("csrs ", imsic_csr!(ireg), ", {}"), in(reg) bit);
+    }
+
+    /// 关闭中断 `id`。
+    ///
+    /// # Safety
+    ///
+    /// 这个函数操作硬件寄存器，必须关中断调用。
+    #[inline]
+    pub unsafe fn disable(id: usize) {
+        let (select, bit) = Self::locate(id);
+        Self::select(select);
+        asm!(concat!("csrc ", imsic_csr!(ireg), ", {}"), in(reg) bit);
+    }
+
+    /// 中断 `id` 的使能位所在的间接寄存器和位。
+    ///
+    /// RV64 上只有偶数编号的 `eie` 寄存器，每个 64 位。
+    #[inline]
+    fn locate(id: usize) -> (usize, usize) {
+        let bits = usize::BITS as usize;
+        (Self::EIE0 + id / bits * (bits / 32), 1 << (id % bits))
+    }
+
+    #[inline]
+    unsafe fn select(select: usize) {
+        asm!(concat!("csrw ", imsic_csr!(iselect), ", {}"), in(reg) select);
+    }
+
+    #[inline]
+    unsafe fn write(select: usize, val: usize) {
+        Self::select(select);
+        asm!(concat!("csrw ", imsic_csr!(ireg), ", {}"), in(reg) val);
+    }
+}
+
+impl IrqController for Imsic {
+    /// 认领优先级最高的挂起中断。
+    #[inline]
+    fn claim(&mut self) -> Option<usize> {
+        let top: usize;
+        unsafe { asm!(concat!("csrrw {}, ", imsic_csr!(topei), ", zero"), out(reg) top) };
+        match top >> 16 {
+            0 => None,
+            id => Some(id),
+        }
+    }
+
+    #[inline]
+    fn complete(&mut self, _irq: usize) {}
+}
+
+/// 通过内存映射访问的 IMSIC 中断文件，用于发送消息信号中断。
+pub struct ImsicFile(usize);
+
+impl ImsicFile {
+    /// 基地址为 `base` 的中断文件。
+    ///
+    /// # Safety
+    ///
+    /// `base` 必须是一个 IMSIC 中断文件的基地址。
+    #[inline]
+    pub const unsafe fn new(base: usize) -> Self {
+        Self(base)
+    }
+
+    /// 中断文件以页为间隔排列时，基地址为 `base` 的一组中断文件中第 `hart` 个。
+    ///
+    /// # Safety
+    ///
+    /// `base` 必须是一组 IMSIC 中断文件的基地址，qemu `virt` 满足这个排列。
+    #[inline]
+    pub const unsafe fn hart(base: usize, hart: usize) -> Self {
+        Self(base + hart * 0x1000)
+    }
+
+    /// 向这个中断文件发送中断 `id`，可以用作核间中断。
+    #[inline]
+    pub fn send(&self, id: usize) {
+        unsafe { (self.0 as *mut u32).write_volatile(id as _) }
+    }
+}
+
+/// 中断源的触发方式。
+#[derive(Clone, Copy)]
+#[repr(u32)]
+pub enum SourceMode {
+    /// 不使用。
+    Inactive = 0,
+    /// 上升沿触发。
+    EdgeRising = 4,
+    /// 下降沿触发。
+    EdgeFalling = 5,
+    /// 高电平触发。
+    LevelHigh = 6,
+    /// 低电平触发。
+    LevelLow = 7,
+}
+
+/// 以消息信号模式工作的 APLIC 中断域。
+///
+/// 把有线中断转换为发往 IMSIC 中断文件的消息。
+pub struct Aplic(usize);
+
+impl Aplic {
+    const DOMAINCFG: usize = 0;
+    const SOURCECFG: usize = 0x4;
+    const MMSIADDRCFG: usize = 0x1bc0;
+    const SETIENUM: usize = 0x1edc;
+    const CLRIENUM: usize = 0x1f5c;
+    const TARGET: usize = 0x3004;
+
+    /// 基地址为 `base` 的中断域。
+    ///
+    /// # Safety
+    ///
+    /// `base` 必须是 APLIC 中断域的基地址。
+    #[inline]
+    pub const unsafe fn new(base: usize) -> Self {
+        Self(base)
+    }
+
+    /// 设置消息的目标地址并打开中断域。
+    ///
+    /// 中断文件以页为间隔排列，`files` 是第 0 个硬件线程的中断文件，共有 `harts` 个。
+    /// 只有 M 态中断域有这个寄存器，它同时决定了子域的目标地址。
+    pub fn init_msi(&self, files: usize, harts: usize) {
+        let ppn = files as u64 >> 12;
+        let lhxw = harts.next_power_of_two().trailing_zeros();
+        self.write(Self::MMSIADDRCFG, ppn as u32);
+        self.write(Self::MMSIADDRCFG + 4, (ppn >> 32) as u32 | lhxw << 12);
+        // IE | DM（消息信号模式）
+        self.write(Self::DOMAINCFG, 1 << 8 | 1 << 2);
+    }
+
+    /// 设置中断源 `source` 的触发方式。
+    #[inline]
+    pub fn configure(&self, source: usize, mode: SourceMode) {
+        self.write(Self::SOURCECFG + (source - 1) * 4, mode as _);
+    }
+
+    /// 把中断源 `source` 委托给第 `child` 个子域。
+    #[inline]
+    pub fn delegate(&self, source: usize, child: usize) {
+        self.write(Self::SOURCECFG + (source - 1) * 4, 1 << 10 | child as u32);
+    }
+
+    /// 中断源 `source` 触发时，向第 `hart` 个硬件线程的中断文件发送中断 `id`。
+    #[inline]
+    pub fn route(&self, source: usize, hart: usize, id: usize) {
+        self.write(Self::TARGET + (source - 1) * 4, (hart << 18 | id) as _);
+    }
+
+    /// 打开中断源 `source`。
+    #[inline]
+    pub fn enable(&self, source: usize) {
+        self.write(Self::SETIENUM, source as _);
+    }
+
+    /// 关闭中断源 `source`。
+    #[inline]
+    pub fn disable(&self, source: usize) {
+        self.write(Self::CLRIENUM, source as _);
+    }
+
+    #[inline]
+    fn write(&self, offset: usize, val: u32) {
+        unsafe { ((self.0 + offset) as *mut u32).write_volatile(val) }
+    }
+}
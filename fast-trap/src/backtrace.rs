@@ -0,0 +1,126 @@
+use crate::{read_scratch, TrapHandler, NEST_GUARD, TARGET};
+use core::{arch::asm, mem::size_of};
+
+/// 回溯的最大深度。
+///
+/// 帧指针链被破坏时可能成环，超过这个深度就停止回溯。
+const MAX_DEPTH: usize = 64;
+
+/// 回溯中的一帧。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StackFrame {
+    /// 函数调用，`pc` 是返回地址。
+    Call {
+        /// 返回地址。
+        pc: usize,
+    },
+    /// 陷入的边界，此后是被第 `level` 级陷入打断的控制流。
+    Trap {
+        /// 陷入的嵌套级别。
+        level: usize,
+        /// 被打断的指令地址。
+        pc: usize,
+    },
+}
+
+/// 沿帧指针回溯调用栈。
+///
+/// 快速路径函数和完整路径函数都以陷入处理器上下文为栈顶调用，
+/// 所以它们的帧指针就是陷入处理器上下文的地址。
+/// 回溯到这样的栈帧时，产生一个 [`StackFrame::Trap`]，
+/// 然后从控制流上下文保存的 `pc`、`s0` 和 `sp` 继续回溯被打断的控制流，嵌套陷入逐级展开。
+///
+/// > **NOTICE** 参与回溯的代码都需要以 `-C force-frame-pointers=yes` 编译。
+/// > 陷入打断函数的序言或尾声时，被打断的函数的调用者可能被跳过。
+pub struct Backtrace {
+    /// 当前栈帧的帧指针。
+    fp: usize,
+    /// 帧指针的下界，帧指针只能向栈顶增长。
+    low: usize,
+    /// 可能遇到的陷入处理器上下文，为 0 表示不在陷入处理中。
+    handler: usize,
+    /// 已产生的帧数。
+    depth: usize,
+}
+
+impl Backtrace {
+    /// 从调用者的栈帧开始回溯。
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let (fp, sp): (usize, usize);
+        unsafe {
+            asm!(
+                "mv {}, s0",
+                "mv {}, sp",
+                out(reg) fp,
+                out(reg) sp,
+                options(nomem, nostack),
+            )
+        };
+        // 在陷入处理中，突发寄存器指向嵌套守卫，否则指向陷入处理器上下文。
+        // 这里只计算地址，遇到相等的帧指针时再检查嵌套守卫。
+        Self {
+            fp,
+            low: sp,
+            handler: read_scratch().wrapping_sub(NEST_GUARD),
+            depth: 0,
+        }
+    }
+}
+
+impl Iterator for Backtrace {
+    type Item = StackFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        const WORD: usize = size_of::<usize>();
+        let fp = self.fp;
+        if fp == 0 || fp % WORD != 0 || fp <= self.low || self.depth >= MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        if fp == self.handler && unsafe { *((fp + NEST_GUARD) as *const usize) } == 0 {
+            // 陷入的边界，转到被打断的控制流
+            let handler = unsafe { &*(fp as *const TrapHandler) };
+            let ctx = unsafe { handler.context.as_ref() };
+            self.fp = ctx.s[0];
+            self.low = ctx.sp;
+            // 嵌套陷入时，恢复时写回突发寄存器的值是外层的嵌套守卫
+            self.handler = if handler.level > 1 {
+                handler.exit_scratch - NEST_GUARD
+            } else {
+                0
+            };
+            Some(StackFrame::Trap {
+                level: handler.level,
+                pc: ctx.pc,
+            })
+        } else {
+            // 帧记录：返回地址和上一帧的帧指针紧挨在帧指针之下
+            let (ra, prev) = unsafe {
+                (
+                    *((fp - WORD) as *const usize),
+                    *((fp - 2 * WORD) as *const usize),
+                )
+            };
+            self.fp = prev;
+            self.low = fp;
+            Some(StackFrame::Call { pc: ra })
+        }
+    }
+}
+
+/// 打印当前调用栈，标出每一级陷入的边界。
+///
+/// 通常在 panic 处理函数中调用。
+pub fn print_backtrace() {
+    log::error!(target: TARGET, "backtrace:");
+    for (i, frame) in Backtrace::capture().enumerate() {
+        match frame {
+            StackFrame::Call { pc } => log::error!(target: TARGET, "{i:4}: {pc:#x}"),
+            StackFrame::Trap { level, pc } => {
+                log::error!(target: TARGET, "---- trap level {level} ----");
+                log::error!(target: TARGET, "{i:4}: {pc:#x}");
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{
+    read_epc, read_tval, write_epc, EntireContextSeparated, FastContext, FastResult, FlowContext,
+};
+
+/// CSR 模拟器。
+///
+/// 用于模拟硬件未实现的 CSR，例如 `time` 或厂商自定义的 CSR。
+pub trait CsrEmulator {
+    /// 读取 `csr`。
+    ///
+    /// 返回 `None` 表示不模拟这个 CSR。
+    fn read(&mut self, csr: u16) -> Option<usize>;
+
+    /// 将 `val` 写入 `csr`。
+    ///
+    /// 返回 `false` 表示这个 CSR 不可写。
+    fn write(&mut self, csr: u16, val: usize) -> bool;
+}
+
+/// CSR 指令的操作。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CsrOp {
+    /// `csrrw`/`csrrwi`。
+    Write,
+    /// `csrrs`/`csrrsi`。
+    Set,
+    /// `csrrc`/`csrrci`。
+    Clear,
+}
+
+/// CSR 指令的源操作数。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CsrSrc {
+    /// 来自寄存器 `rs1`。
+    Reg(u8),
+    /// 5 位无符号立即数。
+    Imm(u8),
+}
+
+/// 译码的 CSR 指令。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CsrInsn {
+    /// 操作。
+    pub op: CsrOp,
+    /// CSR 编号。
+    pub csr: u16,
+    /// 目的寄存器。
+    pub rd: u8,
+    /// 源操作数。
+    pub src: CsrSrc,
+}
+
+/// CSR 指令模拟结果。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CsrEmulation {
+    /// 已模拟，`epc` 已越过这条指令。
+    Done,
+    /// 指令涉及当前路径不能访问的寄存器。
+    ///
+    /// 快速路径中 s0-s11 未保存，需要转到完整路径再模拟。
+    Unreachable,
+    /// 不是 CSR 指令，或模拟器不支持。
+    Illegal,
+}
+
+impl CsrInsn {
+    /// 从指令编码译码。
+    ///
+    /// 不是 CSR 指令则返回 `None`。
+    pub const fn decode(insn: usize) -> Option<Self> {
+        const SYSTEM: usize = 0b111_0011;
+        if insn & 0x7f != SYSTEM {
+            return None;
+        }
+        let rd = ((insn >> 7) & 0x1f) as u8;
+        let rs1 = ((insn >> 15) & 0x1f) as u8;
+        let csr = ((insn >> 20) & 0xfff) as u16;
+        let (op, src) = match (insn >> 12) & 0b111 {
+            0b001 => (CsrOp::Write, CsrSrc::Reg(rs1)),
+            0b010 => (CsrOp::Set, CsrSrc::Reg(rs1)),
+            0b011 => (CsrOp::Clear, CsrSrc::Reg(rs1)),
+            0b101 => (CsrOp::Write, CsrSrc::Imm(rs1)),
+            0b110 => (CsrOp::Set, CsrSrc::Imm(rs1)),
+            0b111 => (CsrOp::Clear, CsrSrc::Imm(rs1)),
+            _ => return None,
+        };
+        Some(Self { op, csr, rd, src })
+    }
+
+    /// 从陷入附加信息译码当前陷入的指令。
+    ///
+    /// > **NOTICE** 要求硬件在非法指令异常时将指令编码写入 `tval`。
+    #[inline]
+    pub fn from_trap() -> Option<Self> {
+        Self::decode(read_tval())
+    }
+
+    /// 指令只访问快速路径中已保存的寄存器。
+    #[inline]
+    pub const fn is_fast(&self) -> bool {
+        let rs1 = match self.src {
+            CsrSrc::Reg(r) => r,
+            CsrSrc::Imm(_) => 0,
+        };
+        is_fast_reg(self.rd) && is_fast_reg(rs1)
+    }
+
+    /// 在控制流上下文上模拟这条指令。
+    ///
+    /// 成功后 `epc` 越过这条指令。
+    pub fn emulate(&self, ctx: &mut FlowContext, emulator: &mut impl CsrEmulator) -> CsrEmulation {
+        let val = match self.src {
+            CsrSrc::Reg(0) => 0,
+            CsrSrc::Reg(r) => match reg(ctx, r) {
+                Some(val) => *val,
+                None => return CsrEmulation::Unreachable,
+            },
+            CsrSrc::Imm(imm) => imm as _,
+        };
+        if self.rd != 0 && reg(ctx, self.rd).is_none() {
+            return CsrEmulation::Unreachable;
+        }
+        // rd 为 x0 的 csrrw 不读 CSR，rs1 为 x0 或立即数为 0 的 csrrs/csrrc 不写 CSR
+        let old = if self.op == CsrOp::Write && self.rd == 0 {
+            0
+        } else {
+            match emulator.read(self.csr) {
+                Some(old) => old,
+                None => return CsrEmulation::Illegal,
+            }
+        };
+        let new = match (self.op, self.src) {
+            (CsrOp::Write, _) => Some(val),
+            (_, CsrSrc::Reg(0) | CsrSrc::Imm(0)) => None,
+            (CsrOp::Set, _) => Some(old | val),
+            (CsrOp::Clear, _) => Some(old & !val),
+        };
+        if let Some(new) = new {
+            if !emulator.write(self.csr, new) {
+                return CsrEmulation::Illegal;
+            }
+        }
+        if self.rd != 0 {
+            *reg(ctx, self.rd).unwrap() = old;
+        }
+        write_epc(read_epc().wrapping_add(4));
+        CsrEmulation::Done
+    }
+}
+
+impl FastContext {
+    /// 在快速路径模拟当前陷入的 CSR 指令。
+    ///
+    /// > **NOTICE** 必须先手工调用 `save_args`，或通过其他方式设置参数寄存器。
+    #[inline]
+    pub fn emulate_csr(&mut self, emulator: &mut impl CsrEmulator) -> CsrEmulation {
+        match CsrInsn::from_trap() {
+            Some(insn) if insn.is_fast() => insn.emulate(self.regs(), emulator),
+            Some(_) => CsrEmulation::Unreachable,
+            None => CsrEmulation::Illegal,
+        }
+    }
+}
+
+impl EntireContextSeparated {
+    /// 在完整路径模拟当前陷入的 CSR 指令。
+    #[inline]
+    pub fn emulate_csr(&mut self, emulator: &mut impl CsrEmulator) -> CsrEmulation {
+        match CsrInsn::from_trap() {
+            Some(insn) => insn.emulate(self.regs(), emulator),
+            None => CsrEmulation::Illegal,
+        }
+    }
+}
+
+/// 快速路径的 CSR 模拟。
+///
+/// 如果指令只涉及 x0、ra、sp、t0-t6 和 a0-a7，在快速路径完成模拟并恢复；
+/// 否则交还上下文，由调用者决定进入完整路径或转发异常。
+///
+/// > **NOTICE** 必须先手工调用 `save_args`，或通过其他方式设置参数寄存器。
+#[inline]
+pub fn fast_emulate_csr(
+    mut ctx: FastContext,
+    emulator: &mut impl CsrEmulator,
+) -> Result<FastResult, (FastContext, CsrEmulation)> {
+    match ctx.emulate_csr(emulator) {
+        CsrEmulation::Done => Ok(ctx.restore()),
+        other => Err((ctx, other)),
+    }
+}
+
+/// 寄存器在快速路径中已保存。
+#[inline]
+const fn is_fast_reg(r: u8) -> bool {
+    matches!(r, 0..=2 | 5..=7 | 10..=17 | 28..=31)
+}
+
+/// 找到通用寄存器在控制流上下文中的位置。
+///
+/// x0、gp、tp 不在上下文中，返回 `None`。
+fn reg(ctx: &mut FlowContext, r: u8) -> Option<&mut usize> {
+    match r {
+        1 => Some(&mut ctx.ra),
+        2 => Some(&mut ctx.sp),
+        5..=7 => Some(&mut ctx.t[r as usize - 5]),
+        8..=9 => Some(&mut ctx.s[r as usize - 8]),
+        10..=17 => Some(&mut ctx.a[r as usize - 10]),
+        18..=27 => Some(&mut ctx.s[r as usize - 16]),
+        28..=31 => Some(&mut ctx.t[r as usize - 25]),
+        _ => None,
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{with_interrupt, EntireContext, EntireResult};
+
+/// 延迟工作。
+pub type DeferredWork = fn(usize);
+
+/// 每个陷入栈上延迟工作队列的容量。
+pub const DEFERRED_CAPACITY: usize = 8;
+
+/// 延迟工作队列已满。
+#[derive(Debug)]
+pub struct QueueFull;
+
+/// 陷入栈上的延迟工作队列。
+///
+/// 快速路径加入的工作在从完整路径恢复前开中断执行。
+/// 所有嵌套级别共用第一级陷入处理器上下文中的队列。
+pub(crate) struct DeferredQueue {
+    items: [Option<(DeferredWork, usize)>; DEFERRED_CAPACITY],
+    head: usize,
+    len: usize,
+    /// 正在执行队列中的工作。
+    ///
+    /// 执行期间发生的嵌套陷入只向队列加入工作，由第一级陷入继续执行。
+    draining: bool,
+}
+
+impl DeferredQueue {
+    pub(crate) const EMPTY: Self = Self {
+        items: [None; DEFERRED_CAPACITY],
+        head: 0,
+        len: 0,
+        draining: false,
+    };
+
+    /// 需要进入完整路径执行队列中的工作。
+    #[inline]
+    pub(crate) fn pending(&self) -> bool {
+        self.len != 0 && !self.draining
+    }
+
+    #[inline]
+    pub(crate) fn push(&mut self, work: DeferredWork, arg: usize) -> Result<(), QueueFull> {
+        if self.len == DEFERRED_CAPACITY {
+            return Err(QueueFull);
+        }
+        self.items[(self.head + self.len) % DEFERRED_CAPACITY] = Some((work, arg));
+        self.len += 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn pop(&mut self) -> Option<(DeferredWork, usize)> {
+        if self.len == 0 {
+            return None;
+        }
+        let item = self.items[self.head].take();
+        self.head = (self.head + 1) % DEFERRED_CAPACITY;
+        self.len -= 1;
+        item
+    }
+
+    /// 开中断执行队列中的所有工作，包括执行期间加入的。
+    ///
+    /// 每项工作在关中断时取出。嵌套陷入可能修改队列，所以不持有引用。
+    pub(crate) fn drain(queue: *mut Self) {
+        unsafe {
+            if !(*queue).pending() {
+                return;
+            }
+            (*queue).draining = true;
+            while let Some((work, arg)) = (*queue).pop() {
+                with_interrupt(|| work(arg));
+            }
+            (*queue).draining = false;
+        }
+    }
+}
+
+/// 只执行延迟工作的完整路径。
+pub(crate) extern "C" fn drain_deferred(ctx: EntireContext) -> EntireResult {
+    let (ctx, _) = ctx.split();
+    ctx.restore()
+}
@@ -0,0 +1,50 @@
+use crate::{FlowContext, FreeTrapStack};
+use core::ops::Range;
+
+/// 双重故障处理函数。
+///
+/// 在应急栈上运行，不能返回。
+pub type DoubleFaultHandler = extern "C" fn(&DoubleFault) -> !;
+
+/// 双重故障现场。
+///
+/// 由陷入处理例程转储在应急栈上。
+#[repr(C)]
+pub struct DoubleFault {
+    /// 发生故障时的通用寄存器和 `pc`。
+    pub context: FlowContext,
+    /// 陷入原因。
+    pub cause: usize,
+    /// 陷入附加信息。
+    pub tval: usize,
+    /// 陷入栈的栈底。
+    bottom: usize,
+    /// 陷入栈的栈顶。
+    top: usize,
+}
+
+impl DoubleFault {
+    /// 发生故障的陷入栈的可用范围。
+    #[inline]
+    pub fn stack(&self) -> Range<usize> {
+        self.bottom..self.top
+    }
+}
+
+impl FreeTrapStack {
+    /// 设置应急栈和双重故障处理函数。
+    ///
+    /// 陷入处理中再次陷入时，如果现场 sp 不在这个陷入栈上，
+    /// 或剩余的栈空间不足以压入嵌套的上下文，就认为发生了双重故障：
+    /// 切换到应急栈，转储故障现场，然后调用 `handler`。
+    /// 只检查现场 sp 的范围：被破坏的 sp 如果仍在外层上下文之下、栈底加上嵌套上下文大小之上，
+    /// 这次陷入会作为普通的嵌套陷入处理，新的上下文压在它指向的位置。
+    ///
+    /// 应急栈只使用一次。没有应急栈或应急栈已用过时，发生双重故障的硬件线程将停机。
+    /// 每个硬件线程应该使用自己的应急栈。
+    pub fn set_double_fault(&mut self, stack: &'static mut [u8], handler: DoubleFaultHandler) {
+        let trap_handler = unsafe { self.0.as_mut() };
+        trap_handler.emergency = stack.as_mut_ptr_range().end as _;
+        trap_handler.double_fault = Some(handler);
+    }
+}
@@ -1,23 +1,33 @@
-﻿use crate::{FlowContext, TrapHandler};
+﻿use crate::{DeferredQueue, FlowContext, TrapHandler};
 use core::{
     marker::PhantomData,
-    mem::{forget, MaybeUninit},
+    mem::{forget, replace, MaybeUninit},
     ops::{Deref, DerefMut},
-    ptr::NonNull,
+    ptr::{addr_of_mut, NonNull},
 };
 
 /// 完整路径函数。
 pub type EntireHandler<T> = extern "C" fn(EntireContext<T>) -> EntireResult;
 
 /// 完整路径上下文。
+///
+/// 快速路径消息放在栈底，嵌套陷入也使用这个位置，所以分离之前不能开中断。
 #[repr(transparent)]
 pub struct EntireContext<T: 'static = ()>(NonNull<TrapHandler>, PhantomData<T>);
 
 impl<T: 'static> EntireContext<T> {
     /// 分离完整路径上下文和快速路径消息。
+    ///
+    /// 消息从栈底移出，此后嵌套陷入可以再使用这个位置。
     #[inline]
     pub fn split(mut self) -> (EntireContextSeparated, FastMail<T>) {
-        let mail = unsafe { &mut *self.0.as_mut().locate_fast_mail() };
+        let mail = unsafe {
+            replace(
+                &mut *self.0.as_mut().locate_fast_mail(),
+                MaybeUninit::uninit(),
+            )
+            .assume_init()
+        };
         let mut handler = self.0;
         forget(self);
         (
@@ -46,24 +56,34 @@ impl EntireContextSeparated {
         unsafe { self.0.context.as_mut() }
     }
 
+    /// 交换上下文指针。
+    #[inline]
+    pub fn swap_context(&mut self, new: NonNull<FlowContext>) -> NonNull<FlowContext> {
+        core::mem::replace(&mut self.0.context, new)
+    }
+
     /// 从完整路径恢复。
+    ///
+    /// 恢复前开中断执行陷入栈上的延迟工作。
+    /// 第一级陷入正在执行延迟工作时，嵌套陷入加入的工作由它继续执行。
     #[inline]
     pub fn restore(self) -> EntireResult {
+        DeferredQueue::drain(unsafe { addr_of_mut!((*self.0.root()).deferred) });
         EntireResult::Restore
     }
 }
 
 /// 快速路径消息。
+///
+/// 未被取走时随它一同释放。
 #[repr(transparent)]
-pub struct FastMail<T: 'static>(&'static mut MaybeUninit<T>);
+pub struct FastMail<T: 'static>(T);
 
 impl<T: 'static> FastMail<T> {
     /// 获取快速路径消息。
     #[inline]
     pub fn get(self) -> T {
-        let ans = unsafe { core::mem::replace(self.0, MaybeUninit::uninit()).assume_init() };
-        forget(self);
-        ans
+        self.0
     }
 }
 
@@ -72,22 +92,14 @@ impl<T: 'static> Deref for FastMail<T> {
 
     #[inline]
     fn deref(&self) -> &Self::Target {
-        unsafe { self.0.assume_init_ref() }
+        &self.0
     }
 }
 
 impl<T: 'static> DerefMut for FastMail<T> {
     #[inline]
     fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { self.0.assume_init_mut() }
-    }
-}
-
-/// 快速路径消息如果未被取走，将自动释放。
-impl<T: 'static> Drop for FastMail<T> {
-    #[inline]
-    fn drop(&mut self) {
-        unsafe { self.0.assume_init_drop() }
+        &mut self.0
     }
 }
 
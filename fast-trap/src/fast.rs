@@ -1,4 +1,6 @@
-﻿use crate::{EntireHandler, FlowContext, TrapHandler};
+﻿use crate::{
+    call_return, drain_deferred, DeferredWork, EntireHandler, FlowContext, QueueFull, TrapHandler,
+};
 use core::{mem::MaybeUninit, ptr::NonNull};
 
 /// 快速路径函数。
@@ -29,12 +31,52 @@ impl FastContext {
         self.0.scratch
     }
 
+    /// 陷入嵌套的级别。
+    ///
+    /// 从陷入栈外发生的陷入是第 1 级，陷入处理中发生的陷入级别依次递增。
+    #[inline]
+    pub fn nesting_level(&self) -> usize {
+        self.0.level
+    }
+
+    /// 访问陷入处理器上下文。
+    #[cfg(feature = "trace")]
+    #[inline]
+    pub(crate) fn handler(&self) -> &TrapHandler {
+        self.0
+    }
+
+    /// 第一级陷入的控制流上下文。
+    ///
+    /// 外层可能正在使用它，所以只返回指针。
+    #[inline]
+    pub(crate) fn root_context(&self) -> NonNull<FlowContext> {
+        unsafe { (*self.0.root()).context }
+    }
+
     /// 获取控制流上下文。
     #[inline]
     pub fn regs(&mut self) -> &mut FlowContext {
         unsafe { self.0.context.as_mut() }
     }
 
+    /// 将参数寄存器保存到控制流上下文。
+    #[allow(clippy::too_many_arguments)]
+    #[inline]
+    pub fn save_args(
+        &mut self,
+        a1: usize,
+        a2: usize,
+        a3: usize,
+        a4: usize,
+        a5: usize,
+        a6: usize,
+        a7: usize,
+    ) {
+        let a0 = self.a0();
+        self.regs().a = [a0, a1, a2, a3, a4, a5, a6, a7];
+    }
+
     /// 交换上下文指针。
     #[inline]
     pub fn swap_context(&mut self, new: NonNull<FlowContext>) -> NonNull<FlowContext> {
@@ -52,12 +94,50 @@ impl FastContext {
         }
     }
 
+    /// 启动新上下文，它返回时调用 `completion`。
+    ///
+    /// 新上下文的返回地址被设置为框架的调用返回例程，参数从控制流上下文的 a0-a7 加载。
+    /// 它返回时如同发生陷入，以返回值 a0/a1 为参数调用 `completion`：
+    /// a0 由 `FastContext::a0` 取得，a1 是第一个参数。
+    /// 此时的陷入原因和返回地址寄存器都没有意义，完成处理函数应该切换到其他上下文。
+    ///
+    /// 只能在第 1 级陷入中使用，否则返回 `Err`。
+    /// 同 [`FastContext::restore`]，如果有延迟工作，先转到完整路径执行它们。
+    ///
+    /// > **NOTICE** 调用返回例程在新上下文的特权级上执行，它要读写状态寄存器和突发寄存器，
+    /// > 所以新上下文必须和陷入处理运行在同一特权级，例如 M 态的陷入处理不能这样调用 U 态的函数。
+    #[inline]
+    pub fn call_with(self, completion: FastHandler) -> Result<FastResult, Self> {
+        if self.0.level != 1 {
+            return Err(self);
+        }
+        self.0.completion = Some(completion);
+        let context = unsafe { self.0.context.as_mut() };
+        context.ra = call_return as usize;
+        unsafe { context.load_others() };
+        Ok(self.restore())
+    }
+
+    /// 加入一项延迟工作，它在从完整路径恢复前开中断地以 `arg` 为参数执行。
+    ///
+    /// 嵌套陷入加入的工作也放在第一级陷入的队列中。
+    #[inline]
+    pub fn defer(&mut self, work: DeferredWork, arg: usize) -> Result<(), QueueFull> {
+        unsafe { (*self.0.root()).deferred.push(work, arg) }
+    }
+
     /// 从快速路径恢复。
     ///
+    /// 如果有延迟工作，转到完整路径执行它们。
+    ///
     /// > **NOTICE** 必须先手工调用 `save_args`，或通过其他方式设置参数寄存器。
     #[inline]
     pub fn restore(self) -> FastResult {
-        FastResult::Restore
+        if unsafe { (*self.0.root()).deferred.pending() } {
+            self.continue_with(drain_deferred, ())
+        } else {
+            FastResult::Restore
+        }
     }
 
     /// 丢弃当前上下文，并直接切换到另一个上下文。
@@ -82,6 +162,7 @@ impl FastContext {
 
 /// 快速路径处理结果。
 #[repr(usize)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum FastResult {
     /// 调用新上下文，只需设置 2 个或更少参数。
     FastCall = 0,
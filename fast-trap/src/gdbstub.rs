@@ -0,0 +1,609 @@
+use crate::{
+    copy_from_user, copy_to_user, preserve_status, read_cause, EntireContext,
+    EntireContextSeparated, EntireResult, FastContext, FastResult, FlowContext, UserFault,
+};
+use core::{arch::asm, slice};
+
+/// 调试器连接。
+///
+/// 以字节为单位收发 GDB 远程协议，例如一个 UART。
+pub trait GdbConnection {
+    /// 阻塞地读取一个字节。
+    fn read(&mut self) -> u8;
+
+    /// 写入一个字节。
+    fn write(&mut self, byte: u8);
+
+    /// 调试器要求终止目标。
+    ///
+    /// 默认以 `panic` 终止。
+    fn kill(&mut self) -> ! {
+        panic!("killed by debugger")
+    }
+}
+
+/// 软件断点。
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    addr: usize,
+    /// 断点指令的长度，2 或 4。
+    kind: usize,
+    /// 被替换的原指令。
+    orig: u32,
+}
+
+impl Breakpoint {
+    /// 在 `addr` 处写入长度为 `kind` 的断点指令。
+    ///
+    /// 无法访问 `addr` 时返回 [`UserFault`]，原指令保持不变。
+    unsafe fn insert(addr: usize, kind: usize) -> Result<Self, UserFault> {
+        const EBREAK: u32 = 0x0010_0073;
+        const C_EBREAK: u32 = 0x9002;
+        let (kind, ebreak) = match kind {
+            2 => (2, C_EBREAK),
+            _ => (4, EBREAK),
+        };
+        let mut orig = [0u8; 4];
+        copy_from_user(&mut orig[..kind], addr as _)?;
+        // 只保证 2 字节对齐，指令可能跨页，只写入一部分时要恢复
+        if let Err(e) = copy_to_user(addr as _, &ebreak.to_le_bytes()[..kind]) {
+            let _ = copy_to_user(addr as _, &orig[..kind]);
+            return Err(e);
+        }
+        asm!("fence.i");
+        Ok(Self {
+            addr,
+            kind,
+            orig: u32::from_le_bytes(orig),
+        })
+    }
+
+    /// 恢复原指令。
+    unsafe fn remove(self) -> Result<(), UserFault> {
+        copy_to_user(self.addr as _, &self.orig.to_le_bytes()[..self.kind])?;
+        asm!("fence.i");
+        Ok(())
+    }
+}
+
+/// GDB 远程协议桩。
+///
+/// 在完整路径中处理断点陷入，通过 [`GdbConnection`] 与调试器通信，
+/// 读写发生陷入的 [`FlowContext`] 和内存。支持最多 `N` 个软件断点、继续和单步。
+///
+/// 单步通过在下一条指令可能的位置放置临时断点实现，分支指令的两个方向都会放置。
+///
+/// 读写内存、设置断点和单步都经过 [`copy_from_user`] 和 [`copy_to_user`]，
+/// 无法访问的地址回复 `E14`。访存异常需要由陷入处理函数修复。
+pub struct GdbStub<C, const N: usize> {
+    connection: C,
+    breakpoints: [Option<Breakpoint>; N],
+    stepping: [Option<Breakpoint>; 2],
+    /// 调试器正在等待目标停止。
+    resumed: bool,
+    buf: [u8; 512],
+}
+
+/// 报告目标因 `SIGTRAP` 停止。
+const STOPPED: &[u8] = b"S05";
+/// 断点异常的陷入原因。
+const BREAKPOINT: usize = 3;
+
+impl<C: GdbConnection, const N: usize> GdbStub<C, N> {
+    /// 构造使用 `connection` 通信的调试桩。
+    #[inline]
+    pub const fn new(connection: C) -> Self {
+        Self {
+            connection,
+            breakpoints: [None; N],
+            stepping: [None; 2],
+            resumed: false,
+            buf: [0; 512],
+        }
+    }
+
+    /// 取得调试器连接。
+    #[inline]
+    pub fn connection(&mut self) -> &mut C {
+        &mut self.connection
+    }
+
+    /// 处理当前的断点陷入，直到调试器要求继续或单步。
+    ///
+    /// 不是断点陷入则返回 `false`。
+    pub fn handle(&mut self, ctx: &mut EntireContextSeparated) -> bool {
+        if read_cause() != BREAKPOINT {
+            return false;
+        }
+        let regs = ctx.regs();
+        unsafe { regs.save_others() };
+        // 修复访存异常的嵌套陷入返回时会改写之前的特权级，恢复后才能回到被调试的控制流
+        unsafe {
+            preserve_status(|| {
+                self.clear_stepping();
+                if core::mem::take(&mut self.resumed) {
+                    self.send(STOPPED);
+                }
+                self.serve(regs);
+            })
+        };
+        unsafe { regs.load_others() };
+        true
+    }
+
+    /// 处理调试器的请求，直到继续执行。
+    fn serve(&mut self, regs: &mut FlowContext) {
+        loop {
+            let len = self.receive();
+            let (cmd, args) = match self.buf[..len].split_first() {
+                Some((cmd, args)) => (*cmd, args),
+                None => continue,
+            };
+            match cmd {
+                b'?' => self.send(STOPPED),
+                b'g' => {
+                    let mut out = Output::new(self);
+                    for n in 0..33 {
+                        out.hex_word(read_reg(regs, n));
+                    }
+                    out.finish();
+                }
+                b'G' => {
+                    let mut args = args;
+                    for n in 0..33 {
+                        let (word, rest) = split_word(args);
+                        write_reg(regs, n, word);
+                        args = rest;
+                    }
+                    self.send(b"OK");
+                }
+                b'p' => match parse_hex(args) {
+                    Some(n) if n < 33 => {
+                        let mut out = Output::new(self);
+                        out.hex_word(read_reg(regs, n));
+                        out.finish();
+                    }
+                    _ => self.send(b""),
+                },
+                b'P' => match split_at(args, b'=') {
+                    Some((n, val)) => match parse_hex(n) {
+                        Some(n) if n < 33 => {
+                            write_reg(regs, n, split_word(val).0);
+                            self.send(b"OK");
+                        }
+                        _ => self.send(b"E01"),
+                    },
+                    None => self.send(b"E01"),
+                },
+                b'm' => match parse_pair(args) {
+                    Some((addr, len)) => self.read_memory(addr, len),
+                    None => self.send(b"E01"),
+                },
+                b'M' => {
+                    let reply = match split_at(args, b':') {
+                        Some((range, data)) => match parse_pair(range) {
+                            Some((addr, len)) if data.len() >= len * 2 => {
+                                write_memory(addr, &data[..len * 2])
+                            }
+                            _ => b"E01",
+                        },
+                        None => b"E01",
+                    };
+                    self.send(reply);
+                }
+                b'Z' | b'z' => {
+                    let reply: &[u8] = match args.split_first() {
+                        Some((b'0', rest)) => match parse_pair(rest.get(1..).unwrap_or(&[])) {
+                            Some((addr, kind)) if cmd == b'Z' => self.insert(addr, kind),
+                            Some((addr, _)) => self.remove(addr),
+                            None => b"E01",
+                        },
+                        _ => b"",
+                    };
+                    self.send(reply);
+                }
+                b'c' => {
+                    if let Some(addr) = parse_hex(args) {
+                        regs.pc = addr;
+                    }
+                    self.resumed = true;
+                    return;
+                }
+                b's' => {
+                    if let Some(addr) = parse_hex(args) {
+                        regs.pc = addr;
+                    }
+                    match self.step(regs) {
+                        Ok(()) => {
+                            self.resumed = true;
+                            return;
+                        }
+                        Err(UserFault) => {
+                            self.clear_stepping();
+                            self.send(b"E14");
+                        }
+                    }
+                }
+                b'D' => {
+                    self.clear();
+                    self.send(b"OK");
+                    return;
+                }
+                b'k' => {
+                    self.clear();
+                    self.connection.kill()
+                }
+                b'H' => self.send(b"OK"),
+                b'q' if args.starts_with(b"Supported") => self.send(b"PacketSize=200"),
+                b'q' if args.starts_with(b"Attached") => self.send(b"1"),
+                _ => self.send(b""),
+            }
+        }
+    }
+
+    /// 在 `addr` 插入断点。
+    fn insert(&mut self, addr: usize, kind: usize) -> &'static [u8] {
+        if self.breakpoints.iter().flatten().any(|bp| bp.addr == addr) {
+            return b"OK";
+        }
+        match self.breakpoints.iter_mut().find(|bp| bp.is_none()) {
+            Some(slot) => match unsafe { Breakpoint::insert(addr, kind) } {
+                Ok(bp) => {
+                    *slot = Some(bp);
+                    b"OK"
+                }
+                Err(UserFault) => b"E14",
+            },
+            None => b"E01",
+        }
+    }
+
+    /// 移除 `addr` 处的断点。
+    fn remove(&mut self, addr: usize) -> &'static [u8] {
+        match self
+            .breakpoints
+            .iter_mut()
+            .find(|bp| matches!(bp, Some(bp) if bp.addr == addr))
+            .and_then(Option::take)
+        {
+            Some(bp) => match unsafe { bp.remove() } {
+                Ok(()) => b"OK",
+                Err(UserFault) => b"E14",
+            },
+            None => b"E01",
+        }
+    }
+
+    /// 移除所有断点。
+    fn clear(&mut self) {
+        for bp in self.breakpoints.iter_mut().filter_map(Option::take) {
+            let _ = unsafe { bp.remove() };
+        }
+    }
+
+    /// 移除单步的临时断点。
+    fn clear_stepping(&mut self) {
+        // 两个临时断点可能重叠，按插入的逆序移除
+        for bp in self.stepping.iter_mut().rev().filter_map(Option::take) {
+            let _ = unsafe { bp.remove() };
+        }
+    }
+
+    /// 回复 `addr` 开始的 `len` 个字节。
+    ///
+    /// 第一个字节就无法访问时回复 `E14`，之后遇到无法访问的字节时只回复之前的部分。
+    fn read_memory(&mut self, addr: usize, len: usize) {
+        let mut byte = 0u8;
+        let read = |i: usize, byte: &mut u8| unsafe {
+            copy_from_user(slice::from_mut(byte), (addr + i) as _).is_ok()
+        };
+        if len > 0 && !read(0, &mut byte) {
+            return self.send(b"E14");
+        }
+        let mut out = Output::new(self);
+        for i in 0..len {
+            if i > 0 && !read(i, &mut byte) {
+                break;
+            }
+            out.hex_byte(byte);
+        }
+        out.finish();
+    }
+
+    /// 在下一条指令可能的位置放置临时断点。
+    fn step(&mut self, regs: &mut FlowContext) -> Result<(), UserFault> {
+        let (a, b) = next_pc(regs)?;
+        let b = b.filter(|&b| b != a);
+        for (slot, addr) in self.stepping.iter_mut().zip([Some(a), b]) {
+            if let Some(addr) = addr {
+                let kind = if is_compressed(addr)? { 2 } else { 4 };
+                *slot = Some(unsafe { Breakpoint::insert(addr, kind) }?);
+            }
+        }
+        Ok(())
+    }
+
+    /// 接收一个数据包，放进缓冲区，返回长度。
+    fn receive(&mut self) -> usize {
+        loop {
+            while self.connection.read() != b'$' {}
+            let mut len = 0;
+            let mut sum = 0u8;
+            loop {
+                match self.connection.read() {
+                    b'#' => break,
+                    byte => {
+                        if len < self.buf.len() {
+                            self.buf[len] = byte;
+                            len += 1;
+                        }
+                        sum = sum.wrapping_add(byte);
+                    }
+                }
+            }
+            let check = [self.connection.read(), self.connection.read()];
+            if hex_digits(&check) as u8 == sum {
+                self.connection.write(b'+');
+                return len;
+            }
+            self.connection.write(b'-');
+        }
+    }
+
+    /// 发送一个数据包。
+    fn send(&mut self, data: &[u8]) {
+        let mut out = Output::new(self);
+        for byte in data {
+            out.byte(*byte);
+        }
+        out.finish();
+    }
+}
+
+/// 边写边计算校验和的数据包。
+struct Output<'a, C: GdbConnection, const N: usize> {
+    stub: &'a mut GdbStub<C, N>,
+    sum: u8,
+}
+
+impl<'a, C: GdbConnection, const N: usize> Output<'a, C, N> {
+    fn new(stub: &'a mut GdbStub<C, N>) -> Self {
+        stub.connection.write(b'$');
+        Self { stub, sum: 0 }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.sum = self.sum.wrapping_add(byte);
+        self.stub.connection.write(byte);
+    }
+
+    fn hex_byte(&mut self, byte: u8) {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        self.byte(HEX[(byte >> 4) as usize]);
+        self.byte(HEX[(byte & 0xf) as usize]);
+    }
+
+    /// 按目标字节序写出一个字。
+    fn hex_word(&mut self, word: usize) {
+        for byte in word.to_le_bytes() {
+            self.hex_byte(byte);
+        }
+    }
+
+    fn finish(mut self) {
+        let sum = self.sum;
+        self.stub.connection.write(b'#');
+        self.sum = 0;
+        self.hex_byte(sum);
+    }
+}
+
+/// 调试器的寄存器编号：0-31 是通用寄存器，32 是 `pc`。
+fn read_reg(regs: &mut FlowContext, n: usize) -> usize {
+    reg(regs, n).map_or(0, |r| *r)
+}
+
+fn write_reg(regs: &mut FlowContext, n: usize, val: usize) {
+    if let Some(r) = reg(regs, n) {
+        *r = val;
+    }
+}
+
+fn reg(regs: &mut FlowContext, n: usize) -> Option<&mut usize> {
+    match n {
+        1 => Some(&mut regs.ra),
+        2 => Some(&mut regs.sp),
+        3 => Some(&mut regs.gp),
+        4 => Some(&mut regs.tp),
+        5..=7 => Some(&mut regs.t[n - 5]),
+        8..=9 => Some(&mut regs.s[n - 8]),
+        10..=17 => Some(&mut regs.a[n - 10]),
+        18..=27 => Some(&mut regs.s[n - 16]),
+        28..=31 => Some(&mut regs.t[n - 25]),
+        32 => Some(&mut regs.pc),
+        _ => None,
+    }
+}
+
+/// 读取 `addr` 处的半字。
+#[inline]
+fn read_half(addr: usize) -> Result<u16, UserFault> {
+    let mut bytes = [0u8; 2];
+    unsafe { copy_from_user(&mut bytes, addr as _) }?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+/// `addr` 处是一条压缩指令。
+#[inline]
+fn is_compressed(addr: usize) -> Result<bool, UserFault> {
+    Ok(read_half(addr)? & 0b11 != 0b11)
+}
+
+/// 计算 `pc` 处指令执行后可能的下一条指令地址。
+fn next_pc(regs: &mut FlowContext) -> Result<(usize, Option<usize>), UserFault> {
+    let pc = regs.pc;
+    let low = read_half(pc)? as usize;
+    if low & 0b11 != 0b11 {
+        let insn = low;
+        let next = pc + 2;
+        let rs1 = (insn >> 7) & 0x1f;
+        return Ok(match (insn >> 13, insn & 0b11) {
+            // c.j，RV32 上还有 c.jal
+            (0b101, 0b01) => (pc.wrapping_add(c_j_imm(insn)), None),
+            #[cfg(target_arch = "riscv32")]
+            (0b001, 0b01) => (pc.wrapping_add(c_j_imm(insn)), None),
+            // c.beqz/c.bnez
+            (0b110 | 0b111, 0b01) => (next, Some(pc.wrapping_add(c_b_imm(insn)))),
+            // c.jr/c.jalr
+            (0b100, 0b10) if rs1 != 0 && (insn >> 2) & 0x1f == 0 => {
+                (read_reg(regs, rs1) & !1, None)
+            }
+            _ => (next, None),
+        });
+    }
+    let insn = low | (read_half(pc + 2)? as usize) << 16;
+    let next = pc + 4;
+    Ok(match insn & 0x7f {
+        // jal
+        0b110_1111 => (pc.wrapping_add(j_imm(insn)), None),
+        // jalr
+        0b110_0111 => {
+            let rs1 = (insn >> 15) & 0x1f;
+            let imm = sign_extend(insn >> 20, 12);
+            (read_reg(regs, rs1).wrapping_add(imm) & !1, None)
+        }
+        // 条件分支
+        0b110_0011 => (next, Some(pc.wrapping_add(b_imm(insn)))),
+        _ => (next, None),
+    })
+}
+
+#[inline]
+fn sign_extend(val: usize, bits: u32) -> usize {
+    let shift = usize::BITS - bits;
+    (((val << shift) as isize) >> shift) as usize
+}
+
+fn j_imm(insn: usize) -> usize {
+    let imm = (insn >> 31 & 1) << 20
+        | (insn >> 12 & 0xff) << 12
+        | (insn >> 20 & 1) << 11
+        | (insn >> 21 & 0x3ff) << 1;
+    sign_extend(imm, 21)
+}
+
+fn b_imm(insn: usize) -> usize {
+    let imm = (insn >> 31 & 1) << 12
+        | (insn >> 7 & 1) << 11
+        | (insn >> 25 & 0x3f) << 5
+        | (insn >> 8 & 0xf) << 1;
+    sign_extend(imm, 13)
+}
+
+fn c_j_imm(insn: usize) -> usize {
+    let imm = (insn >> 12 & 1) << 11
+        | (insn >> 11 & 1) << 4
+        | (insn >> 9 & 0b11) << 8
+        | (insn >> 8 & 1) << 10
+        | (insn >> 7 & 1) << 6
+        | (insn >> 6 & 1) << 7
+        | (insn >> 3 & 0b111) << 1
+        | (insn >> 2 & 1) << 5;
+    sign_extend(imm, 12)
+}
+
+fn c_b_imm(insn: usize) -> usize {
+    let imm = (insn >> 12 & 1) << 8
+        | (insn >> 10 & 0b11) << 3
+        | (insn >> 5 & 0b11) << 6
+        | (insn >> 3 & 0b11) << 1
+        | (insn >> 2 & 1) << 5;
+    sign_extend(imm, 9)
+}
+
+fn hex_digits(digits: &[u8]) -> usize {
+    digits.iter().fold(0, |acc, d| {
+        let d = match d {
+            b'0'..=b'9' => d - b'0',
+            b'a'..=b'f' => d - b'a' + 10,
+            b'A'..=b'F' => d - b'A' + 10,
+            _ => 0,
+        };
+        acc << 4 | d as usize
+    })
+}
+
+fn parse_hex(digits: &[u8]) -> Option<usize> {
+    if digits.is_empty() || digits.len() > usize::BITS as usize / 4 {
+        None
+    } else {
+        Some(hex_digits(digits))
+    }
+}
+
+fn split_at(bytes: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let i = bytes.iter().position(|b| *b == sep)?;
+    Some((&bytes[..i], &bytes[i + 1..]))
+}
+
+/// 解析 `a,b` 形式的一对十六进制数。
+fn parse_pair(bytes: &[u8]) -> Option<(usize, usize)> {
+    let (a, b) = split_at(bytes, b',')?;
+    Some((parse_hex(a)?, parse_hex(b)?))
+}
+
+/// 按目标字节序解析一个字，返回剩余部分。
+fn split_word(hex: &[u8]) -> (usize, &[u8]) {
+    const LEN: usize = core::mem::size_of::<usize>() * 2;
+    let (word, rest) = hex.split_at(hex.len().min(LEN));
+    let mut bytes = [0u8; core::mem::size_of::<usize>()];
+    for (byte, digits) in bytes.iter_mut().zip(word.chunks(2)) {
+        *byte = hex_digits(digits) as u8;
+    }
+    (usize::from_le_bytes(bytes), rest)
+}
+
+/// 把十六进制的 `data` 写到 `addr`，返回回复。
+///
+/// 遇到无法访问的字节时回复 `E14`，之前的字节已经写入。
+fn write_memory(addr: usize, data: &[u8]) -> &'static [u8] {
+    for (i, digits) in data.chunks(2).enumerate() {
+        let byte = hex_digits(digits) as u8;
+        if unsafe { copy_to_user((addr + i) as _, &[byte]) }.is_err() {
+            return b"E14";
+        }
+    }
+    unsafe { asm!("fence.i") };
+    b"OK"
+}
+
+impl FastContext {
+    /// 转到完整路径，由调试桩处理当前的断点陷入。
+    ///
+    /// 不是断点陷入则交还上下文。
+    /// 调试桩读写内存时发生的访存异常会嵌套陷入，快速路径函数应该先尝试 [`fast_fixup_exception`]。
+    ///
+    /// > **NOTICE** 必须先手工调用 `save_args`，或通过其他方式设置参数寄存器。
+    ///
+    /// [`fast_fixup_exception`]: crate::fast_fixup_exception
+    #[inline]
+    pub fn enter_gdb<C: GdbConnection, const N: usize>(
+        self,
+        stub: &'static mut GdbStub<C, N>,
+    ) -> Result<FastResult, Self> {
+        if read_cause() == BREAKPOINT {
+            Ok(self.continue_with(gdb::<C, N>, stub))
+        } else {
+            Err(self)
+        }
+    }
+}
+
+/// 在完整路径运行调试桩。
+extern "C" fn gdb<C: GdbConnection, const N: usize>(
+    ctx: EntireContext<&'static mut GdbStub<C, N>>,
+) -> EntireResult {
+    let (mut ctx, mail) = ctx.split();
+    mail.get().handle(&mut ctx);
+    ctx.restore()
+}
@@ -1,9 +1,15 @@
-﻿use crate::TrapHandler;
-use core::alloc::Layout;
+use crate::{DoubleFault, TrapHandler, NEST_GUARD};
+use core::{alloc::Layout, mem::size_of};
 
 #[cfg(target_arch = "riscv32")]
 #[macro_use]
 mod arch {
+    macro_rules! word {
+        () => {
+            "4"
+        };
+    }
+
     macro_rules! save {
         ($reg:ident => $ptr:ident[$pos:expr]) => {
             concat!(
@@ -35,6 +41,12 @@ mod arch {
 #[cfg(target_arch = "riscv64")]
 #[macro_use]
 mod arch {
+    macro_rules! word {
+        () => {
+            "8"
+        };
+    }
+
     macro_rules! save {
         ($reg:ident => $ptr:ident[$pos:expr]) => {
             concat!(
@@ -64,7 +76,174 @@ mod arch {
     }
 }
 
-use super::{exchange, r#return};
+use super::{csr, exchange, r#return, STATUS_IE};
+
+/// DWARF 寄存器编号。
+macro_rules! dwarf {
+    (ra) => {
+        "1"
+    };
+    (sp) => {
+        "2"
+    };
+    (t0) => {
+        "5"
+    };
+    (t1) => {
+        "6"
+    };
+    (t2) => {
+        "7"
+    };
+    (s0) => {
+        "8"
+    };
+    (s1) => {
+        "9"
+    };
+    (a0) => {
+        "10"
+    };
+    (a1) => {
+        "11"
+    };
+    (a2) => {
+        "12"
+    };
+    (a3) => {
+        "13"
+    };
+    (a4) => {
+        "14"
+    };
+    (a5) => {
+        "15"
+    };
+    (a6) => {
+        "16"
+    };
+    (a7) => {
+        "17"
+    };
+    (s2) => {
+        "18"
+    };
+    (s3) => {
+        "19"
+    };
+    (s4) => {
+        "20"
+    };
+    (s5) => {
+        "21"
+    };
+    (s6) => {
+        "22"
+    };
+    (s7) => {
+        "23"
+    };
+    (s8) => {
+        "24"
+    };
+    (s9) => {
+        "25"
+    };
+    (s10) => {
+        "26"
+    };
+    (s11) => {
+        "27"
+    };
+    (t3) => {
+        "28"
+    };
+    (t4) => {
+        "29"
+    };
+    (t5) => {
+        "30"
+    };
+    (t6) => {
+        "31"
+    };
+}
+
+/// 调用快速路径函数。
+#[cfg(not(feature = "trace"))]
+macro_rules! call_fast_handler {
+    () => {
+        concat!(load!(sp[1] => ra), "\n", "jalr ra")
+    };
+}
+
+/// 通过记录陷入事件的入口调用快速路径函数。
+#[cfg(feature = "trace")]
+macro_rules! call_fast_handler {
+    () => {
+        "call fast_trap_traced_handler"
+    };
+}
+
+/// 两字节的 LEB128 编码。
+///
+/// 只能编码小于 8192 的非负数，有符号和无符号的编码相同。
+macro_rules! leb128 {
+    ($val:expr) => {
+        concat!("(((", $val, ") & 0x7f) | 0x80), ((", $val, ") >> 7)")
+    };
+}
+
+/// 以 DWARF 表达式描述被打断的控制流的寄存器位置。
+///
+/// - `context[pos]`：sp 指向陷入处理器上下文，寄存器在其 `context` 指向的控制流上下文的第 `pos` 个字；
+/// - `base[pos]`：寄存器在 `base` 指向的第 `pos` 个字；
+/// - `csr name`：值在控制状态寄存器中，只用于规范帧地址。
+///
+/// `cfa` 表示规范帧地址，即现场 sp 的值，而不是它的位置。
+macro_rules! cfi {
+    (cfa => context[$pos:literal]) => {
+        concat!(
+            ".cfi_escape 0x0f, 7, 0x72, 0, 0x06, 0x23, ",
+            leb128!(concat!(word!(), "*", $pos)),
+            ", 0x06"
+        )
+    };
+    ($reg:ident => context[$pos:literal]) => {
+        concat!(
+            ".cfi_escape 0x10, ",
+            dwarf!($reg),
+            ", 6, 0x72, 0, 0x06, 0x23, ",
+            leb128!(concat!(word!(), "*", $pos))
+        )
+    };
+    (cfa => csr $csr:ident) => {
+        concat!(
+            ".cfi_escape 0x0f, 4, 0x92, ",
+            leb128!(csr!(dwarf $csr)),
+            ", 0"
+        )
+    };
+    (cfa => $base:ident[$pos:literal]) => {
+        concat!(
+            ".cfi_escape 0x0f, 4, (0x70 + ",
+            dwarf!($base),
+            "), ",
+            leb128!(concat!(word!(), "*", $pos)),
+            ", 0x06"
+        )
+    };
+    ($reg:ident => $base:ident[$pos:literal]) => {
+        concat!(
+            ".cfi_escape 0x10, ",
+            dwarf!($reg),
+            ", 3, (0x70 + ",
+            dwarf!($base),
+            "), ",
+            leb128!(concat!(word!(), "*", $pos))
+        )
+    };
+}
 
 /// 陷入上下文。
 ///
@@ -94,6 +273,21 @@ impl FlowContext {
         sp: 0,
         pc: 0,
     };
+
+    /// 从当前控制流复制 gp 和 tp。
+    #[inline]
+    pub(crate) fn inherit_others(&mut self) {
+        unsafe {
+            core::arch::asm!(
+                "   mv {gp}, gp
+                    mv {tp}, tp
+                ",
+                gp = out(reg) self.gp,
+                tp = out(reg) self.tp,
+                options(nomem, nostack),
+            )
+        };
+    }
 }
 
 /// 把当前栈复用为陷入栈，预留 Handler 空间。
@@ -115,16 +309,462 @@ pub unsafe extern "C" fn reuse_stack_for_trap() {
     )
 }
 
-/// 陷入处理例程。
+extern "C" {
+    /// 陷入处理例程。
+    ///
+    /// # Safety
+    ///
+    /// 不要直接调用这个函数。暴露它仅仅是为了提供其入口的符号链接。
+    #[link_name = "fast_trap_entry"]
+    pub fn trap_entry();
+
+    /// 陷入返回例程。
+    ///
+    /// 按处理函数的返回值恢复现场，或调用完整路径函数。
+    ///
+    /// | reg | position
+    /// | --- | -
+    /// | a0  | 处理结果
+    /// | sp  | 陷入处理器上下文
+    #[link_name = "fast_trap_exit"]
+    fn trap_exit();
+}
+
+// 陷入处理例程和陷入返回例程。
+//
+// 编译器只在生成调试信息时为裸函数生成调用帧，其中不能稳定地写 `.cfi_*` 指令，
+// 所以用全局汇编实现，自己描述调用帧：
+// 被打断的控制流是陷入处理的调用者，返回列（ra）是现场 pc，规范帧地址是现场 sp，
+// 各寄存器按所处的阶段保存在控制流上下文、陷入处理器上下文或嵌套守卫中。
+// 现场 ra 和现场 pc 共用返回列，只能描述后者。
+core::arch::global_asm!(
+    "   .pushsection .text.fast_trap_entry, \"ax\", @progbits
+        .globl fast_trap_entry
+        .globl fast_trap_exit
+        .p2align 2
+        .type fast_trap_entry, @function
+    fast_trap_entry:
+        .cfi_startproc
+        .cfi_signal_frame
+    ",
+    // 现场 pc 在 epc 中
+    concat!(".cfi_register ra, ", csr!(dwarf epc)),
+    // 换栈
+    //
+    // 第一级陷入时，突发寄存器指向陷入处理器上下文；
+    // 在陷入处理中，突发寄存器指向嵌套守卫，其首字为 0。
+    exchange!(),
+    cfi!(cfa => csr scratch),
+    // 加载上下文指针
+    save!(a0 => sp[2]),
+    load!(sp[0] => a0),
+    cfi!(a0 => sp[2]),
+    ".cfi_remember_state",
+    "beqz a0, 8f",
+    // 保存尽量少的寄存器
+    save!(ra => a0[0]),
+    save!(t0 => a0[1]),
+    save!(t1 => a0[2]),
+    save!(t2 => a0[3]),
+    save!(t3 => a0[4]),
+    save!(t4 => a0[5]),
+    save!(t5 => a0[6]),
+    save!(t6 => a0[7]),
+    cfi!(t0 => a0[1]),
+    // 保存帧指针和现场 pc，回溯从这里跨过陷入
+    save!(s0 => a0[16]),
+    concat!("csrr t0, ", csr!(epc)),
+    save!(t0 => a0[31]),
+    // 保存现场 sp，然后令突发寄存器指向嵌套守卫
+    concat!("csrr t0, ", csr!(scratch)),
+    save!(t0 => a0[30]),
+    cfi!(cfa => a0[30]),
+    "addi t0, sp, {guard}",
+    concat!("csrw ", csr!(scratch), ", t0"),
+    // 调用快速路径函数
+    //
+    // | reg    | position
+    // | ------ | -
+    // | ra     | `TrapHandler.context`
+    // | t0-t6  | `TrapHandler.context`
+    // | a0     | `TrapHandler.scratch`
+    // | a1-a7  | 参数寄存器
+    // | sp     | `TrapHandler.context`
+    // | pc     | `TrapHandler.context`
+    // | gp, tp | gp, tp
+    // | s0     | `TrapHandler.context`
+    // | s1-s11 | 不支持
+    //
+    // 快速路径函数以陷入处理器上下文为栈顶，
+    // 所以其帧指针就是陷入处理器上下文的地址，回溯据此识别陷入的边界。
+    //
+    // > 若要保留陷入上下文，
+    // > 必须在快速路径保存 a0-a7 到 `TrapHandler.context`，
+    // > 并进入完整路径执行后续操作。
+    // >
+    // > 若要切换上下文，在快速路径设置 gp/tp/sepc 和 sstatus。
+    "9:",
+    // 参数寄存器会被快速路径函数改写，不能回溯
+    cfi!(cfa => context[30]),
+    cfi!(ra => context[31]),
+    cfi!(t0 => context[1]),
+    cfi!(t1 => context[2]),
+    cfi!(t2 => context[3]),
+    cfi!(t3 => context[4]),
+    cfi!(t4 => context[5]),
+    cfi!(t5 => context[6]),
+    cfi!(t6 => context[7]),
+    cfi!(s0 => context[16]),
+    cfi!(a0 => sp[2]),
+    ".cfi_undefined a1",
+    ".cfi_undefined a2",
+    ".cfi_undefined a3",
+    ".cfi_undefined a4",
+    ".cfi_undefined a5",
+    ".cfi_undefined a6",
+    ".cfi_undefined a7",
+    "mv   a0, sp",
+    call_fast_handler!(),
+    "j    fast_trap_exit",
+    "8:", // 嵌套陷入
+    //
+    // | reg        | position
+    // | ---------- | -
+    // | sp         | 外层的嵌套守卫
+    // | a0         | 外层的嵌套守卫
+    // | 突发寄存器 | 现场 sp
+    //
+    ".cfi_restore_state",
+    save!(t0 => sp[1]),
+    save!(t1 => sp[3]),
+    cfi!(t0 => sp[1]),
+    cfi!(t1 => sp[3]),
+    ".cfi_remember_state",
+    "addi t0, sp, -{guard}",
+    concat!("csrr a0, ", csr!(scratch)),
+    // 现场 sp 必须在外层的栈上，且剩余的空间足够压入新的上下文，否则是双重故障
+    "bgtu a0, t0, 7f
+     addi a0, a0, -{frame}
+     andi a0, a0, {mask}
+    ",
+    load!(t0[11] => t1),
+    "bltu a0, t1, 7f",
+    // 在当前栈上压入新的陷入处理器上下文和控制流上下文
+    "addi t1, a0, {handler}",
+    save!(ra => t1[0]),
+    load!(sp[1] => ra),
+    save!(ra => t1[1]),
+    load!(sp[3] => ra),
+    save!(ra => t1[2]),
+    save!(t2 => t1[3]),
+    save!(t3 => t1[4]),
+    save!(t4 => t1[5]),
+    save!(t5 => t1[6]),
+    save!(t6 => t1[7]),
+    save!(s0 => t1[16]),
+    concat!("csrr ra, ", csr!(epc)),
+    save!(ra => t1[31]),
+    concat!("csrr ra, ", csr!(scratch)),
+    save!(ra => t1[30]),
+    // 初始化新的陷入处理器上下文
+    save!(t1 => a0[0]),
+    load!(t0[1] => ra),
+    save!(ra => a0[1]),
+    load!(sp[2] => ra),
+    save!(ra => a0[2]),
+    load!(t0[3] => ra),
+    save!(ra => a0[3]),
+    load!(t0[4] => ra),
+    save!(ra => a0[4]),
+    save!(zero => a0[5]),
+    save!(sp => a0[9]),
+    load!(t0[10] => ra),
+    "addi ra, ra, 1",
+    save!(ra => a0[10]),
+    load!(t0[11] => ra),
+    save!(ra => a0[11]),
+    load!(t0[12] => ra),
+    save!(ra => a0[12]),
+    load!(t0[13] => ra),
+    save!(ra => a0[13]),
+    // 完成处理函数只在第一级使用；延迟工作队列和其他字段只在第一级有效，不必初始化
+    save!(zero => a0[14]),
+    // 换到新的陷入处理器上下文
+    "mv   sp, a0",
+    cfi!(cfa => context[30]),
+    cfi!(t0 => context[1]),
+    cfi!(t1 => context[2]),
+    "addi t0, sp, {guard}",
+    concat!("csrw ", csr!(scratch), ", t0"),
+    "j    9b",
+    "7:", // 双重故障
+    //
+    // | reg        | position
+    // | ---------- | -
+    // | t0, t1, a0 | 外层的嵌套守卫
+    // | 突发寄存器 | 现场 sp
+    // | else       | 现场
+    //
+    // t0 指向外层的陷入处理器上下文。
+    ".cfi_restore_state",
+    ".cfi_remember_state",
+    // 应急栈只使用一次，没有应急栈则停机
+    load!(t0[12] => a0),
+    "beqz a0, 6f",
+    save!(zero => t0[12]),
+    "addi a0, a0, -{fault}
+     andi a0, a0, -16
+    ",
+    // 在应急栈上转储故障现场
+    save!(ra => a0[0]),
+    load!(sp[1] => ra),
+    save!(ra => a0[1]),
+    load!(sp[3] => ra),
+    save!(ra => a0[2]),
+    save!(t2 => a0[3]),
+    save!(t3 => a0[4]),
+    save!(t4 => a0[5]),
+    save!(t5 => a0[6]),
+    save!(t6 => a0[7]),
+    load!(sp[2] => ra),
+    save!(ra => a0[8]),
+    save!(a1 => a0[9]),
+    save!(a2 => a0[10]),
+    save!(a3 => a0[11]),
+    save!(a4 => a0[12]),
+    save!(a5 => a0[13]),
+    save!(a6 => a0[14]),
+    save!(a7 => a0[15]),
+    save!(s0 => a0[16]),
+    save!(s1 => a0[17]),
+    save!(s2 => a0[18]),
+    save!(s3 => a0[19]),
+    save!(s4 => a0[20]),
+    save!(s5 => a0[21]),
+    save!(s6 => a0[22]),
+    save!(s7 => a0[23]),
+    save!(s8 => a0[24]),
+    save!(s9 => a0[25]),
+    save!(s10 => a0[26]),
+    save!(s11 => a0[27]),
+    save!(gp => a0[28]),
+    save!(tp => a0[29]),
+    concat!("csrr ra, ", csr!(scratch)),
+    save!(ra => a0[30]),
+    concat!("csrr ra, ", csr!(epc)),
+    save!(ra => a0[31]),
+    concat!("csrr ra, ", csr!(cause)),
+    save!(ra => a0[32]),
+    concat!("csrr ra, ", csr!(tval)),
+    save!(ra => a0[33]),
+    load!(t0[11] => ra),
+    save!(ra => a0[34]),
+    save!(t0 => a0[35]),
+    // 调用双重故障处理函数
+    load!(t0[13] => ra),
+    "mv   sp, a0",
+    // 转储的前 32 个字与控制流上下文的布局相同
+    cfi!(cfa => sp[30]),
+    cfi!(ra => sp[31]),
+    cfi!(t0 => sp[1]),
+    cfi!(t1 => sp[2]),
+    cfi!(t2 => sp[3]),
+    cfi!(t3 => sp[4]),
+    cfi!(t4 => sp[5]),
+    cfi!(t5 => sp[6]),
+    cfi!(t6 => sp[7]),
+    cfi!(a0 => sp[8]),
+    cfi!(a1 => sp[9]),
+    cfi!(a2 => sp[10]),
+    cfi!(a3 => sp[11]),
+    cfi!(a4 => sp[12]),
+    cfi!(a5 => sp[13]),
+    cfi!(a6 => sp[14]),
+    cfi!(a7 => sp[15]),
+    "jalr ra",
+    "6:", // 停机
+    ".cfi_restore_state",
+    "   wfi
+        j   6b
+        .cfi_endproc
+        .size fast_trap_entry, . - fast_trap_entry
+    ",
+    // 陷入返回例程
+    //
+    // 默认所有寄存器按控制流上下文恢复，返回到 epc。
+    // 快速路径直接返回时 s1-s11 不从上下文加载，仍在寄存器中。
+    "   .p2align 2
+        .type fast_trap_exit, @function
+    fast_trap_exit:
+        .cfi_startproc
+        .cfi_signal_frame
+    ",
+    concat!(".cfi_register ra, ", csr!(dwarf epc)),
+    cfi!(cfa => context[30]),
+    cfi!(t0 => context[1]),
+    cfi!(t1 => context[2]),
+    cfi!(t2 => context[3]),
+    cfi!(t3 => context[4]),
+    cfi!(t4 => context[5]),
+    cfi!(t5 => context[6]),
+    cfi!(t6 => context[7]),
+    cfi!(a0 => context[8]),
+    cfi!(a1 => context[9]),
+    cfi!(a2 => context[10]),
+    cfi!(a3 => context[11]),
+    cfi!(a4 => context[12]),
+    cfi!(a5 => context[13]),
+    cfi!(a6 => context[14]),
+    cfi!(a7 => context[15]),
+    cfi!(s0 => context[16]),
+    "0:", // 加载上下文指针
+    load!(sp[0] => a1),
+    // 0：设置少量参数寄存器
+    "   beqz  a0, 0f",
+    // 1：设置所有参数寄存器
+    "   addi  a0, a0, -1
+        beqz  a0, 1f
+    ",
+    // 2：设置所有调用者寄存器
+    "   addi  a0, a0, -1
+        beqz  a0, 2f
+    ",
+    // 3：设置所有寄存器
+    "   addi  a0, a0, -1
+        beqz  a0, 3f
+    ",
+    // 4：完整路径
+    ".cfi_remember_state",
+    save!(s0  => a1[16]),
+    save!(s1  => a1[17]),
+    save!(s2  => a1[18]),
+    save!(s3  => a1[19]),
+    save!(s4  => a1[20]),
+    save!(s5  => a1[21]),
+    save!(s6  => a1[22]),
+    save!(s7  => a1[23]),
+    save!(s8  => a1[24]),
+    save!(s9  => a1[25]),
+    save!(s10 => a1[26]),
+    save!(s11 => a1[27]),
+    // 调用完整路径函数
+    //
+    // | reg    | position
+    // | ------ | -
+    // | gp, tp | gp, tp
+    // | else   | `TrapHandler.context`
+    //
+    // > 若要保留陷入上下文，
+    // > 在完整路径中保存 gp/tp/pc 到 `TrapHandler.context`。
+    // >
+    // > 若要切换上下文，在完整路径设置 gp/tp/sepc 和 sstatus。
+    //
+    // 完整路径函数可能开中断，嵌套陷入会改写 epc，所以按上下文中的 pc 回溯。
+    cfi!(ra => context[31]),
+    cfi!(s1 => context[17]),
+    cfi!(s2 => context[18]),
+    cfi!(s3 => context[19]),
+    cfi!(s4 => context[20]),
+    cfi!(s5 => context[21]),
+    cfi!(s6 => context[22]),
+    cfi!(s7 => context[23]),
+    cfi!(s8 => context[24]),
+    cfi!(s9 => context[25]),
+    cfi!(s10 => context[26]),
+    cfi!(s11 => context[27]),
+    "mv   a0, sp",
+    load!(sp[2] => ra),
+    "jalr ra",
+    "j    0b",
+    "3:", // 设置所有寄存器
+    ".cfi_restore_state",
+    load!(a1[16] => s0),
+    load!(a1[17] => s1),
+    load!(a1[18] => s2),
+    load!(a1[19] => s3),
+    load!(a1[20] => s4),
+    load!(a1[21] => s5),
+    load!(a1[22] => s6),
+    load!(a1[23] => s7),
+    load!(a1[24] => s8),
+    load!(a1[25] => s9),
+    load!(a1[26] => s10),
+    load!(a1[27] => s11),
+    "2:", // 设置所有调用者寄存器
+    load!(a1[ 0] => ra),
+    load!(a1[ 1] => t0),
+    load!(a1[ 2] => t1),
+    load!(a1[ 3] => t2),
+    load!(a1[ 4] => t3),
+    load!(a1[ 5] => t4),
+    load!(a1[ 6] => t5),
+    load!(a1[ 7] => t6),
+    "1:", // 设置所有参数寄存器
+    load!(a1[10] => a2),
+    load!(a1[11] => a3),
+    load!(a1[12] => a4),
+    load!(a1[13] => a5),
+    load!(a1[14] => a6),
+    load!(a1[15] => a7),
+    "0:", // 设置少量参数寄存器
+    // 除 a0、a1 和 sp 外，寄存器都已经是恢复后的值
+    ".cfi_restore t0",
+    ".cfi_restore t1",
+    ".cfi_restore t2",
+    ".cfi_restore t3",
+    ".cfi_restore t4",
+    ".cfi_restore t5",
+    ".cfi_restore t6",
+    ".cfi_restore a2",
+    ".cfi_restore a3",
+    ".cfi_restore a4",
+    ".cfi_restore a5",
+    ".cfi_restore a6",
+    ".cfi_restore a7",
+    ".cfi_restore s0",
+    // 恢复突发寄存器
+    load!(sp[9] => a0),
+    concat!("csrw ", csr!(scratch), ", a0"),
+    load!(a1[ 8] => a0),
+    ".cfi_restore a0",
+    cfi!(cfa => a1[30]),
+    cfi!(a1 => a1[9]),
+    load!(a1[30] => sp),
+    ".cfi_def_cfa sp, 0",
+    load!(a1[ 9] => a1),
+    ".cfi_restore a1",
+    r#return!(),
+    "   .cfi_endproc
+        .size fast_trap_exit, . - fast_trap_exit
+        .popsection
+    ",
+    guard   = const NEST_GUARD,
+    frame   = const FRAME,
+    mask    = const !(HANDLER.align() as isize - 1),
+    handler = const HANDLER.size(),
+    fault   = const size_of::<DoubleFault>(),
+);
+
+/// 陷入处理器上下文的布局。
+const HANDLER: Layout = Layout::new::<TrapHandler>();
+/// 嵌套陷入时压入的栈帧大小。
+const FRAME: usize = HANDLER.size() + size_of::<FlowContext>();
+
+/// 调用返回例程。
+///
+/// `FastContext::call_with` 启动的上下文返回时进入这里，
+/// 如同发生陷入，但以 a0/a1 为参数调用完成处理函数。
 ///
 /// # Safety
 ///
-/// 不要直接调用这个函数。暴露它仅仅是为了提供其入口的符号链接。
+/// 不要直接调用这个函数。
 #[naked]
-pub unsafe extern "C" fn trap_entry() {
+pub(crate) unsafe extern "C" fn call_return() {
     core::arch::asm!(
         ".align 2",
-        // 换栈
+        // 关中断，防止保存现场时发生陷入
+        concat!("csrci ", csr!(status), ", {ie}"),
+        // 换栈，突发寄存器一定指向第一级的陷入处理器上下文
         exchange!(),
         // 加载上下文指针
         save!(a0 => sp[2]),
@@ -138,105 +778,23 @@ pub unsafe extern "C" fn trap_entry() {
         save!(t4 => a0[5]),
         save!(t5 => a0[6]),
         save!(t6 => a0[7]),
-        // 调用快速路径函数
-        //
-        // | reg    | position
-        // | ------ | -
-        // | ra     | `TrapHandler.context`
-        // | t0-t6  | `TrapHandler.context`
-        // | a0     | `TrapHandler.scratch`
-        // | a1-a7  | 参数寄存器
-        // | sp     | sscratch
-        // | gp, tp | gp, tp
-        // | s0-s11 | 不支持
-        //
-        // > 若要保留陷入上下文，
-        // > 必须在快速路径保存 a0-a7 到 `TrapHandler.context`，
-        // > 并进入完整路径执行后续操作。
-        // >
-        // > 若要切换上下文，在快速路径设置 gp/tp/sscratch/sepc 和 sstatus。
+        // 保存帧指针，以返回地址为现场 pc，回溯从这里跨过调用返回
+        save!(s0 => a0[16]),
+        save!(ra => a0[31]),
+        // 保存现场 sp，然后令突发寄存器指向嵌套守卫
+        concat!("csrr t0, ", csr!(scratch)),
+        save!(t0 => a0[30]),
+        "addi t0, sp, {guard}",
+        concat!("csrw ", csr!(scratch), ", t0"),
+        // 调用完成处理函数，它只使用一次
         "mv   a0, sp",
-        load!(sp[1] => ra),
+        load!(sp[14] => ra),
+        save!(zero => sp[14]),
         "jalr ra",
-        "0:", // 加载上下文指针
-        load!(sp[0] => a1),
-        // 0：设置少量参数寄存器
-        "   beqz  a0, 0f",
-        // 1：设置所有参数寄存器
-        "   addi  a0, a0, -1
-            beqz  a0, 1f
-        ",
-        // 2：设置所有调用者寄存器
-        "   addi  a0, a0, -1
-            beqz  a0, 2f
-        ",
-        // 3：设置所有寄存器
-        "   addi  a0, a0, -1
-            beqz  a0, 3f
-        ",
-        // 4：完整路径
-        save!(s0  => a1[16]),
-        save!(s1  => a1[17]),
-        save!(s2  => a1[18]),
-        save!(s3  => a1[19]),
-        save!(s4  => a1[20]),
-        save!(s5  => a1[21]),
-        save!(s6  => a1[22]),
-        save!(s7  => a1[23]),
-        save!(s8  => a1[24]),
-        save!(s9  => a1[25]),
-        save!(s10 => a1[26]),
-        save!(s11 => a1[27]),
-        // 调用完整路径函数
-        //
-        // | reg    | position
-        // | ------ | -
-        // | sp     | sscratch
-        // | gp, tp | gp, tp
-        // | else   | `TrapHandler.context`
-        //
-        // > 若要保留陷入上下文，
-        // > 在完整路径中保存 gp/tp/sp/pc 到 `TrapHandler.context`。
-        // >
-        // > 若要切换上下文，在完整路径设置 gp/tp/sscratch/sepc 和 sstatus。
-        "mv   a0, sp",
-        load!(sp[2] => ra),
-        "jalr ra",
-        "j    0b",
-        "3:", // 设置所有寄存器
-        load!(a1[16] => s0),
-        load!(a1[17] => s1),
-        load!(a1[18] => s2),
-        load!(a1[19] => s3),
-        load!(a1[20] => s4),
-        load!(a1[21] => s5),
-        load!(a1[22] => s6),
-        load!(a1[23] => s7),
-        load!(a1[24] => s8),
-        load!(a1[25] => s9),
-        load!(a1[26] => s10),
-        load!(a1[27] => s11),
-        "2:", // 设置所有调用者寄存器
-        load!(a1[ 0] => ra),
-        load!(a1[ 1] => t0),
-        load!(a1[ 2] => t1),
-        load!(a1[ 3] => t2),
-        load!(a1[ 4] => t3),
-        load!(a1[ 5] => t4),
-        load!(a1[ 6] => t5),
-        load!(a1[ 7] => t6),
-        "1:", // 设置所有参数寄存器
-        load!(a1[10] => a2),
-        load!(a1[11] => a3),
-        load!(a1[12] => a4),
-        load!(a1[13] => a5),
-        load!(a1[14] => a6),
-        load!(a1[15] => a7),
-        "0:", // 设置少量参数寄存器
-        load!(a1[ 8] => a0),
-        load!(a1[ 9] => a1),
-        exchange!(),
-        r#return!(),
+        "j    {exit}",
+        ie    = const STATUS_IE,
+        guard = const NEST_GUARD,
+        exit  =   sym trap_exit,
         options(noreturn),
     )
 }
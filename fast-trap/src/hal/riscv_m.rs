@@ -1,4 +1,5 @@
 ﻿use super::{trap_entry, FlowContext};
+use crate::{FastContext, FastResult};
 use core::arch::asm;
 
 macro_rules! exchange {
@@ -7,30 +8,127 @@ macro_rules! exchange {
     };
 }
 
+macro_rules! csr {
+    (scratch) => {
+        "mscratch"
+    };
+    (epc) => {
+        "mepc"
+    };
+    (cause) => {
+        "mcause"
+    };
+    (tval) => {
+        "mtval"
+    };
+    (status) => {
+        "mstatus"
+    };
+    // DWARF 寄存器编号，是控制状态寄存器编号加 4096
+    (dwarf scratch) => {
+        "0x1340"
+    };
+    (dwarf epc) => {
+        "0x1341"
+    };
+}
+
 macro_rules! r#return {
     () => {
         "mret"
     };
 }
 
-pub(super) use {exchange, r#return};
+pub(super) use {csr, exchange, r#return};
+
+/// 状态寄存器中的中断使能位。
+pub(super) const STATUS_IE: usize = 1 << 3;
 
 impl FlowContext {
     /// 从上下文向硬件加载非调用规范约定的寄存器。
     #[inline]
     pub(crate) unsafe fn load_others(&self) {
         asm!(
-            "   mv         gp, {gp}
-                mv         tp, {tp}
-                csrw mscratch, {sp}
-                csrw     mepc, {pc}
+            "   mv     gp, {gp}
+                mv     tp, {tp}
+                csrw mepc, {pc}
             ",
             gp = in(reg) self.gp,
             tp = in(reg) self.tp,
-            sp = in(reg) self.sp,
             pc = in(reg) self.pc,
         );
     }
+
+    /// 从硬件向上下文保存非调用规范约定的寄存器。
+    #[inline]
+    pub(crate) unsafe fn save_others(&mut self) {
+        asm!(
+            "   mv   {gp}, gp
+                mv   {tp}, tp
+                csrr {pc}, mepc
+            ",
+            gp = out(reg) self.gp,
+            tp = out(reg) self.tp,
+            pc = out(reg) self.pc,
+        );
+    }
+}
+
+impl FastContext {
+    /// 将当前陷入转发到 S 态。
+    ///
+    /// 设置 `sepc`、`scause`、`stval` 和 `sstatus` 的 SPP/SPIE/SIE，然后从 `stvec` 继续执行。
+    /// 若 `stvec` 是向量模式且 `cause` 是中断，跳转到中断对应的向量。
+    ///
+    /// > **NOTICE** 必须先手工调用 `save_args`，或通过其他方式设置参数寄存器。
+    #[inline]
+    pub fn redirect_to_supervisor(self, cause: usize, tval: usize) -> FastResult {
+        unsafe { redirect_to_supervisor(cause, tval) };
+        self.restore()
+    }
+}
+
+/// 设置 S 态陷入现场，并将 `mepc` 指向 S 态陷入入口。
+#[inline]
+unsafe fn redirect_to_supervisor(cause: usize, tval: usize) {
+    const SIE: usize = 1 << 1;
+    const SPIE: usize = 1 << 5;
+    const SPP: usize = 1 << 8;
+    const MPP: usize = 0b11 << 11;
+    const MPP_SUPERVISOR: usize = 0b01 << 11;
+    const INTERRUPT: usize = 1 << (usize::BITS - 1);
+
+    let mstatus: usize;
+    let stvec: usize;
+    asm!(
+        "   csrr {status}, mstatus
+            csrr {tvec},   stvec
+            csrw sepc,     {epc}
+            csrw scause,   {cause}
+            csrw stval,    {tval}
+        ",
+        status = out(reg) mstatus,
+        tvec   = out(reg) stvec,
+        epc    = in(reg) read_epc(),
+        cause  = in(reg) cause,
+        tval   = in(reg) tval,
+    );
+    // 陷入前不是 U 态，则 S 态陷入前视作 S 态
+    let mut status = mstatus & !(SIE | SPIE | SPP | MPP) | MPP_SUPERVISOR;
+    if mstatus & MPP != 0 {
+        status |= SPP;
+    }
+    if mstatus & SIE != 0 {
+        status |= SPIE;
+    }
+    asm!("csrw mstatus, {}", in(reg) status);
+    // 向量模式下，中断跳转到 base + 4 × code
+    let base = stvec & !0b11;
+    if stvec & 0b11 == 1 && cause & INTERRUPT != 0 {
+        write_epc(base + 4 * (cause & !INTERRUPT));
+    } else {
+        write_epc(base);
+    }
 }
 
 /// 交换突发寄存器。
@@ -40,6 +138,99 @@ pub(crate) fn exchange_scratch(mut val: usize) -> usize {
     val
 }
 
+/// 读取突发寄存器。
+#[inline]
+pub(crate) fn read_scratch() -> usize {
+    let ans: usize;
+    unsafe { asm!("csrr {}, mscratch", out(reg) ans, options(nomem)) };
+    ans
+}
+
+/// 时钟中断的陷入原因。
+pub(crate) const TIMER_INTERRUPT: usize = 1 << (usize::BITS - 1) | 7;
+
+/// 读取陷入原因。
+#[inline]
+pub(crate) fn read_cause() -> usize {
+    let ans: usize;
+    unsafe { asm!("csrr {}, mcause", out(reg) ans, options(nomem)) };
+    ans
+}
+
+/// 打开时钟中断。
+///
+/// 返回之前的中断使能状态，用于 `restore_timer_interrupt`。
+#[inline]
+pub(crate) unsafe fn enable_timer_interrupt() -> usize {
+    const MTIE: usize = 1 << 7;
+    const MIE: usize = 1 << 3;
+    let (enable, status): (usize, usize);
+    asm!(
+        "   csrrs  {enable}, mie,     {tie}
+            csrrsi {status}, mstatus, {ie}
+        ",
+        enable = out(reg) enable,
+        status = out(reg) status,
+        tie    = in(reg) MTIE,
+        ie     = const MIE,
+    );
+    enable & MTIE | status & MIE
+}
+
+/// 恢复 `enable_timer_interrupt` 之前的中断使能状态。
+#[inline]
+pub(crate) unsafe fn restore_timer_interrupt(state: usize) {
+    const MTIE: usize = 1 << 7;
+    const MIE: usize = 1 << 3;
+    asm!(
+        "   csrc mstatus, {ie}
+            csrc mie,     {tie}
+        ",
+        ie  = in(reg) MIE & !state,
+        tie = in(reg) MTIE & !state,
+    );
+}
+
+/// 读取周期计数器。
+#[cfg(feature = "trace")]
+#[inline]
+pub(crate) fn read_cycle() -> usize {
+    let ans: usize;
+    unsafe { asm!("csrr {}, mcycle", out(reg) ans, options(nomem)) };
+    ans
+}
+
+/// 读取陷入附加信息。
+#[inline]
+pub(crate) fn read_tval() -> usize {
+    let ans: usize;
+    unsafe { asm!("csrr {}, mtval", out(reg) ans, options(nomem)) };
+    ans
+}
+
+/// 读取陷入返回地址。
+#[inline]
+pub(crate) fn read_epc() -> usize {
+    let ans: usize;
+    unsafe { asm!("csrr {}, mepc", out(reg) ans, options(nomem)) };
+    ans
+}
+
+/// 陷入前的特权级是用户态。
+#[inline]
+pub(crate) fn trapped_from_user() -> bool {
+    const MPP: usize = 0b11 << 11;
+    let status: usize;
+    unsafe { asm!("csrr {}, mstatus", out(reg) status, options(nomem)) };
+    status & MPP == 0
+}
+
+/// 设置陷入返回地址。
+#[inline]
+pub(crate) fn write_epc(val: usize) {
+    unsafe { asm!("csrw mepc, {}", in(reg) val, options(nomem)) };
+}
+
 /// 模拟一个 `cause` 类的陷入。
 ///
 /// # Safety
@@ -60,6 +251,74 @@ pub unsafe fn soft_trap(cause: usize) {
     );
 }
 
+/// 关闭中断，执行 `request` 在内存中写入请求，然后模拟一个陷入。
+///
+/// 不改写陷入原因，陷入处理函数从内存中取得请求。
+///
+/// # Safety
+///
+/// 如同发生一个陷入。
+#[inline]
+pub(crate) unsafe fn soft_trap_with(request: impl FnOnce()) {
+    const MIE: usize = 1 << 3;
+    asm!("csrci mstatus, {}", const MIE);
+    request();
+    asm!(
+        "   la   {0},   1f
+            csrw mepc,  {0}
+            j    {trap}
+         1:
+        ",
+        out(reg) _,
+        trap = sym trap_entry,
+    );
+}
+
+/// 令陷入返回到当前特权级，并在返回后打开中断。
+#[inline]
+pub(crate) unsafe fn return_with_interrupt() {
+    const MPP: usize = 0b11 << 11;
+    const MPIE: usize = 1 << 7;
+    asm!("csrs mstatus, {}", in(reg) MPP | MPIE);
+}
+
+/// 打开中断执行 `f`。
+///
+/// 嵌套陷入会改写 mepc 和 mstatus，在这里保存，执行后关中断并恢复。
+#[inline]
+pub(crate) unsafe fn with_interrupt(f: impl FnOnce()) {
+    const MIE: usize = 1 << 3;
+    let (epc, status): (usize, usize);
+    asm!(
+        "   csrr   {epc},    mepc
+            csrrsi {status}, mstatus, {ie}
+        ",
+        epc    = out(reg) epc,
+        status = out(reg) status,
+        ie     = const MIE,
+    );
+    f();
+    asm!(
+        "   csrw mstatus, {status}
+            csrw mepc,   {epc}
+        ",
+        status = in(reg) status,
+        epc    = in(reg) epc,
+    );
+}
+
+/// 执行 `f`，然后恢复 mstatus。
+///
+/// 嵌套陷入返回时会改写之前的特权级和中断使能，在这里保存，执行后恢复。
+#[cfg(feature = "gdbstub")]
+#[inline]
+pub(crate) unsafe fn preserve_status(f: impl FnOnce()) {
+    let status: usize;
+    asm!("csrr {}, mstatus", out(reg) status);
+    f();
+    asm!("csrw mstatus, {}", in(reg) status);
+}
+
 /// 设置全局陷入入口。
 ///
 /// # Safety
@@ -69,3 +328,11 @@ pub unsafe fn soft_trap(cause: usize) {
 pub unsafe fn load_direct_trap_entry() {
     asm!("csrw mtvec, {0}", in(reg) trap_entry, options(nomem))
 }
+
+/// 允许当前特权级访问用户页，执行 `f`。
+///
+/// M 态直接访问物理地址，不需要额外的设置。
+#[inline]
+pub(crate) fn with_user_access(f: impl FnOnce()) {
+    f()
+}
@@ -7,30 +7,70 @@ macro_rules! exchange {
     };
 }
 
+macro_rules! csr {
+    (scratch) => {
+        "sscratch"
+    };
+    (epc) => {
+        "sepc"
+    };
+    (cause) => {
+        "scause"
+    };
+    (tval) => {
+        "stval"
+    };
+    (status) => {
+        "sstatus"
+    };
+    // DWARF 寄存器编号，是控制状态寄存器编号加 4096
+    (dwarf scratch) => {
+        "0x1140"
+    };
+    (dwarf epc) => {
+        "0x1141"
+    };
+}
+
 macro_rules! r#return {
     () => {
         "sret"
     };
 }
 
-pub(super) use {exchange, r#return};
+pub(super) use {csr, exchange, r#return};
+
+/// 状态寄存器中的中断使能位。
+pub(super) const STATUS_IE: usize = 1 << 1;
 
 impl FlowContext {
     /// 从上下文向硬件加载非调用规范约定的寄存器。
     #[inline]
     pub(crate) unsafe fn load_others(&self) {
         asm!(
-            "   mv         gp, {gp}
-                mv         tp, {tp}
-                csrw sscratch, {sp}
-                csrw     sepc, {pc}
+            "   mv     gp, {gp}
+                mv     tp, {tp}
+                csrw sepc, {pc}
             ",
             gp = in(reg) self.gp,
             tp = in(reg) self.tp,
-            sp = in(reg) self.sp,
             pc = in(reg) self.pc,
         );
     }
+
+    /// 从硬件向上下文保存非调用规范约定的寄存器。
+    #[inline]
+    pub(crate) unsafe fn save_others(&mut self) {
+        asm!(
+            "   mv   {gp}, gp
+                mv   {tp}, tp
+                csrr {pc}, sepc
+            ",
+            gp = out(reg) self.gp,
+            tp = out(reg) self.tp,
+            pc = out(reg) self.pc,
+        );
+    }
 }
 
 /// 交换突发寄存器。
@@ -40,6 +80,99 @@ pub(crate) fn exchange_scratch(mut val: usize) -> usize {
     val
 }
 
+/// 读取突发寄存器。
+#[inline]
+pub(crate) fn read_scratch() -> usize {
+    let ans: usize;
+    unsafe { asm!("csrr {}, sscratch", out(reg) ans, options(nomem)) };
+    ans
+}
+
+/// 时钟中断的陷入原因。
+pub(crate) const TIMER_INTERRUPT: usize = 1 << (usize::BITS - 1) | 5;
+
+/// 读取陷入原因。
+#[inline]
+pub(crate) fn read_cause() -> usize {
+    let ans: usize;
+    unsafe { asm!("csrr {}, scause", out(reg) ans, options(nomem)) };
+    ans
+}
+
+/// 打开时钟中断。
+///
+/// 返回之前的中断使能状态，用于 `restore_timer_interrupt`。
+#[inline]
+pub(crate) unsafe fn enable_timer_interrupt() -> usize {
+    const STIE: usize = 1 << 5;
+    const SIE: usize = 1 << 1;
+    let (enable, status): (usize, usize);
+    asm!(
+        "   csrrs  {enable}, sie,     {tie}
+            csrrsi {status}, sstatus, {ie}
+        ",
+        enable = out(reg) enable,
+        status = out(reg) status,
+        tie    = in(reg) STIE,
+        ie     = const SIE,
+    );
+    enable & STIE | status & SIE
+}
+
+/// 恢复 `enable_timer_interrupt` 之前的中断使能状态。
+#[inline]
+pub(crate) unsafe fn restore_timer_interrupt(state: usize) {
+    const STIE: usize = 1 << 5;
+    const SIE: usize = 1 << 1;
+    asm!(
+        "   csrc sstatus, {ie}
+            csrc sie,     {tie}
+        ",
+        ie  = in(reg) SIE & !state,
+        tie = in(reg) STIE & !state,
+    );
+}
+
+/// 读取周期计数器。
+#[cfg(feature = "trace")]
+#[inline]
+pub(crate) fn read_cycle() -> usize {
+    let ans: usize;
+    unsafe { asm!("csrr {}, cycle", out(reg) ans, options(nomem)) };
+    ans
+}
+
+/// 读取陷入附加信息。
+#[inline]
+pub(crate) fn read_tval() -> usize {
+    let ans: usize;
+    unsafe { asm!("csrr {}, stval", out(reg) ans, options(nomem)) };
+    ans
+}
+
+/// 读取陷入返回地址。
+#[inline]
+pub(crate) fn read_epc() -> usize {
+    let ans: usize;
+    unsafe { asm!("csrr {}, sepc", out(reg) ans, options(nomem)) };
+    ans
+}
+
+/// 陷入前的特权级是用户态。
+#[inline]
+pub(crate) fn trapped_from_user() -> bool {
+    const SPP: usize = 1 << 8;
+    let status: usize;
+    unsafe { asm!("csrr {}, sstatus", out(reg) status, options(nomem)) };
+    status & SPP == 0
+}
+
+/// 设置陷入返回地址。
+#[inline]
+pub(crate) fn write_epc(val: usize) {
+    unsafe { asm!("csrw sepc, {}", in(reg) val, options(nomem)) };
+}
+
 /// 模拟一个 `cause` 类的陷入。
 ///
 /// # Safety
@@ -60,6 +193,74 @@ pub unsafe fn soft_trap(cause: usize) {
     );
 }
 
+/// 关闭中断，执行 `request` 在内存中写入请求，然后模拟一个陷入。
+///
+/// 不改写陷入原因，陷入处理函数从内存中取得请求。
+///
+/// # Safety
+///
+/// 如同发生一个陷入。
+#[inline]
+pub(crate) unsafe fn soft_trap_with(request: impl FnOnce()) {
+    const SIE: usize = 1 << 1;
+    asm!("csrci sstatus, {}", const SIE);
+    request();
+    asm!(
+        "   la   {0},   1f
+            csrw sepc,  {0}
+            j    {trap}
+         1:
+        ",
+        out(reg) _,
+        trap = sym trap_entry,
+    );
+}
+
+/// 令陷入返回到当前特权级，并在返回后打开中断。
+#[inline]
+pub(crate) unsafe fn return_with_interrupt() {
+    const SPP: usize = 1 << 8;
+    const SPIE: usize = 1 << 5;
+    asm!("csrs sstatus, {}", in(reg) SPP | SPIE);
+}
+
+/// 打开中断执行 `f`。
+///
+/// 嵌套陷入会改写 sepc 和 sstatus，在这里保存，执行后关中断并恢复。
+#[inline]
+pub(crate) unsafe fn with_interrupt(f: impl FnOnce()) {
+    const SIE: usize = 1 << 1;
+    let (epc, status): (usize, usize);
+    asm!(
+        "   csrr   {epc},    sepc
+            csrrsi {status}, sstatus, {ie}
+        ",
+        epc    = out(reg) epc,
+        status = out(reg) status,
+        ie     = const SIE,
+    );
+    f();
+    asm!(
+        "   csrw sstatus, {status}
+            csrw sepc,   {epc}
+        ",
+        status = in(reg) status,
+        epc    = in(reg) epc,
+    );
+}
+
+/// 执行 `f`，然后恢复 sstatus。
+///
+/// 嵌套陷入返回时会改写之前的特权级和中断使能，在这里保存，执行后恢复。
+#[cfg(feature = "gdbstub")]
+#[inline]
+pub(crate) unsafe fn preserve_status(f: impl FnOnce()) {
+    let status: usize;
+    asm!("csrr {}, sstatus", out(reg) status);
+    f();
+    asm!("csrw sstatus, {}", in(reg) status);
+}
+
 /// 设置全局陷入入口。
 ///
 /// # Safety
@@ -69,3 +270,15 @@ pub unsafe fn soft_trap(cause: usize) {
 pub unsafe fn load_direct_trap_entry() {
     asm!("csrw stvec, {0}", in(reg) trap_entry, options(nomem))
 }
+
+/// 允许当前特权级访问用户页，执行 `f`。
+///
+/// 执行前置起 `sstatus.SUM`，执行后恢复原来的状态。
+#[inline]
+pub(crate) fn with_user_access(f: impl FnOnce()) {
+    const SUM: usize = 1 << 18;
+    let status: usize;
+    unsafe { asm!("csrrs {}, sstatus, {}", out(reg) status, in(reg) SUM) };
+    f();
+    unsafe { asm!("csrc sstatus, {}", in(reg) SUM & !status) };
+}
@@ -0,0 +1,277 @@
+use crate::{FastContext, FastResult, FlowContext};
+use core::{arch::asm, ptr::NonNull};
+
+/// IPC 消息，通过 a1-a6 传递。
+pub type Message = [usize; 6];
+
+/// 发送并等待回复。
+pub const IPC_CALL: usize = 0;
+/// 只发送。
+pub const IPC_SEND: usize = 1;
+/// 回复调用者，然后等待接收。
+pub const IPC_REPLY_RECV: usize = 2;
+
+/// 参与 IPC 的任务。
+///
+/// 陷入栈的上下文指针必须指向 `IpcTask::context`，IPC 操作从上下文找到任务。
+#[repr(C)]
+pub struct IpcTask {
+    /// 任务的控制流上下文。
+    pub context: FlowContext,
+    /// 在端点队列中的下一个任务。
+    next: Option<NonNull<IpcTask>>,
+    /// 等待回复的调用者。
+    reply_to: Option<NonNull<IpcTask>>,
+    /// 发送后等待回复。
+    calling: bool,
+}
+
+impl IpcTask {
+    /// 以 `context` 为初始状态构造任务。
+    #[inline]
+    pub const fn new(context: FlowContext) -> Self {
+        Self {
+            context,
+            next: None,
+            reply_to: None,
+            calling: false,
+        }
+    }
+
+    /// 接收消息，标记 IPC 成功。
+    #[inline]
+    fn deliver(&mut self, msg: &Message) {
+        self.context.a[0] = 0;
+        self.context.a[1..7].copy_from_slice(msg);
+    }
+}
+
+/// 侵入式任务队列。
+struct Queue {
+    head: Option<NonNull<IpcTask>>,
+    tail: Option<NonNull<IpcTask>>,
+}
+
+impl Queue {
+    const EMPTY: Self = Self {
+        head: None,
+        tail: None,
+    };
+
+    fn push(&mut self, mut task: NonNull<IpcTask>) {
+        unsafe { task.as_mut() }.next = None;
+        match self.tail.replace(task) {
+            Some(mut tail) => unsafe { tail.as_mut() }.next = Some(task),
+            None => self.head = Some(task),
+        }
+    }
+
+    fn pop(&mut self) -> Option<NonNull<IpcTask>> {
+        let mut head = self.head?;
+        self.head = unsafe { head.as_mut() }.next.take();
+        if self.head.is_none() {
+            self.tail = None;
+        }
+        Some(head)
+    }
+}
+
+/// IPC 端点。
+///
+/// 保存等待接收和等待发送的任务队列，两个队列不会同时非空。
+pub struct Endpoint {
+    receivers: Queue,
+    senders: Queue,
+}
+
+impl Endpoint {
+    /// 构造空的端点。
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            receivers: Queue::EMPTY,
+            senders: Queue::EMPTY,
+        }
+    }
+}
+
+/// IPC 唤醒的任务交给调度器。
+pub trait IpcScheduler {
+    /// `task` 转为就绪。
+    fn ready(&mut self, task: NonNull<IpcTask>);
+}
+
+/// 在快速路径中处理发送并等待回复。
+///
+/// 有任务等待接收时，把消息复制到接收者的上下文并切换过去；
+/// 否则当前任务排入端点的发送队列，返回 `Err`，快速路径需要调度其他任务。
+///
+/// 这些 IPC 操作都假设当前陷入是 [`ipc_syscall`] 发起的 `ecall`。
+///
+/// # Safety
+///
+/// 陷入栈的上下文指针必须指向一个 [`IpcTask`] 的 `context`，端点中的任务必须都有效。
+pub unsafe fn call(
+    mut ctx: FastContext,
+    ep: &mut Endpoint,
+    msg: Message,
+) -> Result<FastResult, FastContext> {
+    let mut current = suspend(&mut ctx);
+    unsafe { current.as_mut() }.calling = true;
+    match ep.receivers.pop() {
+        Some(mut receiver) => {
+            let receiver = unsafe { receiver.as_mut() };
+            receiver.deliver(&msg);
+            receiver.reply_to = Some(current);
+            Ok(ctx.switch_to(NonNull::from(&mut receiver.context)))
+        }
+        None => {
+            unsafe { current.as_mut() }.context.a[1..7].copy_from_slice(&msg);
+            ep.senders.push(current);
+            Err(ctx)
+        }
+    }
+}
+
+/// 在快速路径中处理只发送。
+///
+/// 有任务等待接收时，把消息复制到接收者的上下文并切换过去，当前任务交给调度器；
+/// 否则当前任务排入端点的发送队列，返回 `Err`，快速路径需要调度其他任务。
+///
+/// # Safety
+///
+/// 陷入栈的上下文指针必须指向一个 [`IpcTask`] 的 `context`，端点中的任务必须都有效。
+pub unsafe fn send(
+    mut ctx: FastContext,
+    ep: &mut Endpoint,
+    msg: Message,
+    sched: &mut impl IpcScheduler,
+) -> Result<FastResult, FastContext> {
+    let mut current = suspend(&mut ctx);
+    unsafe { current.as_mut() }.calling = false;
+    match ep.receivers.pop() {
+        Some(mut receiver) => {
+            let receiver = unsafe { receiver.as_mut() };
+            receiver.deliver(&msg);
+            receiver.reply_to = None;
+            unsafe { current.as_mut() }.context.a[0] = 0;
+            sched.ready(current);
+            Ok(ctx.switch_to(NonNull::from(&mut receiver.context)))
+        }
+        None => {
+            unsafe { current.as_mut() }.context.a[1..7].copy_from_slice(&msg);
+            ep.senders.push(current);
+            Err(ctx)
+        }
+    }
+}
+
+/// 在快速路径中处理回复并等待接收。
+///
+/// 如果当前任务有等待回复的调用者，把 `msg` 复制给调用者。
+/// 然后如果有任务等待发送，直接接收它的消息；否则当前任务排入端点的接收队列。
+///
+/// 当前任务阻塞时，切换到调用者；没有调用者则返回 `Err`，快速路径需要调度其他任务。
+/// 当前任务继续运行时，调用者交给调度器。
+///
+/// # Safety
+///
+/// 陷入栈的上下文指针必须指向一个 [`IpcTask`] 的 `context`，端点中的任务必须都有效。
+pub unsafe fn reply_recv(
+    mut ctx: FastContext,
+    ep: &mut Endpoint,
+    msg: Message,
+    sched: &mut impl IpcScheduler,
+) -> Result<FastResult, FastContext> {
+    let mut current = suspend(&mut ctx);
+    let current = unsafe { current.as_mut() };
+    let caller = current.reply_to.take().map(|mut caller| {
+        unsafe { caller.as_mut() }.deliver(&msg);
+        caller
+    });
+    match ep.senders.pop() {
+        Some(mut sender) => {
+            let sender = unsafe { sender.as_mut() };
+            let msg = [
+                sender.context.a[1],
+                sender.context.a[2],
+                sender.context.a[3],
+                sender.context.a[4],
+                sender.context.a[5],
+                sender.context.a[6],
+            ];
+            current.deliver(&msg);
+            if sender.calling {
+                current.reply_to = Some(NonNull::from(&mut *sender));
+            } else {
+                sender.context.a[0] = 0;
+                sched.ready(NonNull::from(sender));
+            }
+            if let Some(caller) = caller {
+                sched.ready(caller);
+            }
+            unsafe { current.context.load_others() };
+            Ok(ctx.restore())
+        }
+        None => {
+            ep.receivers.push(NonNull::from(current));
+            match caller {
+                Some(mut caller) => {
+                    Ok(ctx.switch_to(NonNull::from(&mut unsafe { caller.as_mut() }.context)))
+                }
+                None => Err(ctx),
+            }
+        }
+    }
+}
+
+/// 保存当前任务的非调用规范约定的寄存器，并越过 `ecall`。
+///
+/// 上下文指针必须指向一个 [`IpcTask`] 的 `context`。
+#[inline]
+unsafe fn suspend(ctx: &mut FastContext) -> NonNull<IpcTask> {
+    let context = ctx.regs();
+    context.save_others();
+    context.pc += 4;
+    NonNull::from(context).cast()
+}
+
+/// 在任务中发起 IPC 系统调用。
+///
+/// a7 传递调用号 `op`，a0 传递端点 `ep`，a1-a6 传递消息，返回时 a1-a6 是收到的消息。
+/// IPC 不保存 s 寄存器，s0 和 s1 在这里借 t5 和 t6 保存，其余的 s 寄存器声明为被破坏。
+#[inline]
+pub fn ipc_syscall(op: usize, ep: usize, msg: Message) -> Message {
+    let mut ans = msg;
+    unsafe {
+        asm!(
+            "   mv    t5, s0
+                mv    t6, s1
+                ecall
+                mv    s0, t5
+                mv    s1, t6
+            ",
+            inlateout("a0") ep => _,
+            inlateout("a1") msg[0] => ans[0],
+            inlateout("a2") msg[1] => ans[1],
+            inlateout("a3") msg[2] => ans[2],
+            inlateout("a4") msg[3] => ans[3],
+            inlateout("a5") msg[4] => ans[4],
+            inlateout("a6") msg[5] => ans[5],
+            inlateout("a7") op => _,
+            out("t5") _,
+            out("t6") _,
+            out("s2") _,
+            out("s3") _,
+            out("s4") _,
+            out("s5") _,
+            out("s6") _,
+            out("s7") _,
+            out("s8") _,
+            out("s9") _,
+            out("s10") _,
+            out("s11") _,
+        )
+    };
+    ans
+}
@@ -0,0 +1,146 @@
+use crate::{FastContext, FastResult};
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Waker,
+};
+
+/// 中断控制器。
+pub trait IrqController {
+    /// 认领一个挂起的中断，没有挂起的中断时返回 `None`。
+    fn claim(&mut self) -> Option<usize>;
+
+    /// 通知中断控制器 `irq` 已处理完成。
+    fn complete(&mut self, irq: usize);
+}
+
+/// 中断源到 `Waker` 的映射。
+///
+/// 异步驱动为等待的中断源注册 `Waker`，快速路径中认领中断后唤醒它们。
+/// 中断源编号超出 `N` 时不能注册，这样的中断被认领后直接完成。
+pub struct IrqWakers<const N: usize>([WakerSlot; N]);
+
+impl<const N: usize> IrqWakers<N> {
+    /// 构造空的映射。
+    #[inline]
+    pub const fn new() -> Self {
+        Self([WakerSlot::EMPTY; N])
+    }
+
+    /// 为中断源 `irq` 注册 `waker`，替换之前注册的。
+    ///
+    /// 如果注册时恰好发生了 `irq` 中断，`waker` 将被立即唤醒。
+    /// 返回是否注册成功，`irq` 超出 `N` 时什么也不做并返回 `false`。
+    #[inline]
+    pub fn register(&self, irq: usize, waker: &Waker) -> bool {
+        match self.0.get(irq) {
+            Some(slot) => {
+                slot.register(waker);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 唤醒为中断源 `irq` 注册的 `Waker`。
+    ///
+    /// 唤醒后注册被清除，返回是否有 `Waker` 被唤醒。
+    #[inline]
+    pub fn wake(&self, irq: usize) -> bool {
+        match self.0.get(irq).and_then(WakerSlot::take) {
+            Some(waker) => {
+                waker.wake();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 在快速路径中认领并完成所有挂起的中断，唤醒等待的任务，然后直接恢复。
+    ///
+    /// `Waker` 在陷入栈上关中断地调用，只应该做标记就绪之类的轻量操作。
+    ///
+    /// > **NOTICE** 必须先手工调用 `save_args`，或通过其他方式设置参数寄存器。
+    #[inline]
+    pub fn wake_and_restore(
+        &self,
+        ctx: FastContext,
+        controller: &mut impl IrqController,
+    ) -> FastResult {
+        while let Some(irq) = controller.claim() {
+            self.wake(irq);
+            controller.complete(irq);
+        }
+        ctx.restore()
+    }
+}
+
+/// 可以在中断中取走的 `Waker` 槽。
+///
+/// 注册和唤醒互不等待：唤醒发生在注册过程中时，由注册者负责唤醒。
+struct WakerSlot {
+    state: AtomicUsize,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+unsafe impl Sync for WakerSlot {}
+
+impl WakerSlot {
+    const WAITING: usize = 0;
+    const REGISTERING: usize = 1;
+    const WAKING: usize = 2;
+
+    /// 只用于初始化数组。
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        state: AtomicUsize::new(Self::WAITING),
+        waker: UnsafeCell::new(None),
+    };
+
+    fn register(&self, waker: &Waker) {
+        match self.state.compare_exchange(
+            Self::WAITING,
+            Self::REGISTERING,
+            Ordering::Acquire,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => {
+                let slot = unsafe { &mut *self.waker.get() };
+                match slot {
+                    Some(old) if old.will_wake(waker) => {}
+                    _ => *slot = Some(waker.clone()),
+                }
+                if self
+                    .state
+                    .compare_exchange(
+                        Self::REGISTERING,
+                        Self::WAITING,
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                    )
+                    .is_err()
+                {
+                    // 注册过程中发生了唤醒
+                    let waker = slot.take();
+                    self.state.swap(Self::WAITING, Ordering::AcqRel);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+            }
+            Err(Self::WAKING) => waker.wake_by_ref(),
+            Err(_) => {}
+        }
+    }
+
+    fn take(&self) -> Option<Waker> {
+        match self.state.fetch_or(Self::WAKING, Ordering::AcqRel) {
+            Self::WAITING => {
+                let waker = unsafe { &mut *self.waker.get() }.take();
+                self.state.fetch_and(!Self::WAKING, Ordering::Release);
+                waker
+            }
+            _ => None,
+        }
+    }
+}
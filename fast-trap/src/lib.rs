@@ -4,29 +4,75 @@
 #![feature(naked_functions, asm_const)]
 #![deny(warnings, missing_docs)]
 
+mod aia;
+mod backtrace;
+mod csr_emulation;
+mod deferred;
+mod double_fault;
 mod entire;
 mod fast;
+#[cfg(feature = "gdbstub")]
+mod gdbstub;
 mod hal;
+mod ipc;
+mod irq_wakers;
+mod page_fault;
+mod plic;
+mod preempt;
+mod remote;
+mod signal;
+#[cfg(feature = "trace")]
+mod stats;
+mod thread;
+mod timer;
+#[cfg(feature = "trace")]
+mod trace;
+mod uaccess;
 
+pub use aia::*;
+pub use backtrace::*;
+pub use csr_emulation::*;
+pub use deferred::*;
+pub use double_fault::*;
 pub use entire::*;
 pub use fast::*;
+#[cfg(feature = "gdbstub")]
+pub use gdbstub::*;
 pub use hal::*;
+pub use ipc::*;
+pub use irq_wakers::*;
+pub use page_fault::*;
+pub use plic::*;
+pub use preempt::*;
+pub use remote::*;
+pub use signal::*;
+#[cfg(feature = "trace")]
+pub use stats::*;
+pub use thread::*;
+pub use timer::*;
+#[cfg(feature = "trace")]
+pub use trace::*;
+pub use uaccess::*;
 
 use core::{
     alloc::Layout,
     marker::PhantomPinned,
-    mem::{align_of, forget, MaybeUninit},
+    mem::{align_of, forget, size_of, MaybeUninit},
     ops::Range,
-    ptr::{drop_in_place, NonNull},
+    ptr::{drop_in_place, write_bytes, NonNull},
+    slice,
 };
 
 const TARGET: &str = "fast-trap";
 
+/// 填充栈空间的字节。
+const STACK_PAINT: u8 = 0xa5;
+
 /// 游离的陷入栈。
 pub struct FreeTrapStack(NonNull<TrapHandler>);
 
 /// 已加载的陷入栈。
-pub struct LoadedTrapStack(usize);
+pub struct LoadedTrapStack(usize, NonNull<TrapHandler>);
 
 /// 构造陷入栈失败。
 #[derive(Debug)]
@@ -49,6 +95,19 @@ impl FreeTrapStack {
             handler.context = context_ptr;
             handler.fast_handler = fast_handler;
             handler.block = NonNull::from(&block);
+            handler.nest = NestGuard::ZERO;
+            handler.exit_scratch = ptr;
+            handler.level = 1;
+            handler.bottom = bottom;
+            handler.emergency = 0;
+            handler.double_fault = None;
+            handler.completion = None;
+            handler.deferred = DeferredQueue::EMPTY;
+            #[cfg(feature = "trace")]
+            {
+                handler.trace = None;
+                handler.stats = None;
+            }
             forget(block);
             log::trace!(target: TARGET, "new TrapStack({:?})", range);
             Ok(Self(unsafe { NonNull::new_unchecked(handler) }))
@@ -57,13 +116,37 @@ impl FreeTrapStack {
         }
     }
 
+    /// 在内存块上构造游离的陷入栈，并以固定的字节填充栈空间。
+    ///
+    /// 这样构造的陷入栈可以通过 `high_water_mark` 测量栈的最大用量。
+    ///
+    /// > **NOTICE** 不要在正在使用的栈上调用，填充会覆盖栈上的数据。
+    pub fn new_painted(
+        block: impl TrapStackBlock,
+        context_ptr: NonNull<FlowContext>,
+        fast_handler: FastHandler,
+    ) -> Result<Self, IllegalStack> {
+        let ans = Self::new(block, context_ptr, fast_handler)?;
+        unsafe { ans.0.as_ref().paint() };
+        Ok(ans)
+    }
+
     /// 将这个陷入栈加载为预备陷入栈。
     #[inline]
     pub fn load(self) -> LoadedTrapStack {
         log::trace!("load TrapStack({:#x?})", unsafe { self.0.as_ref().range() });
         let scratch = exchange_scratch(self.0.as_ptr() as _);
+        let handler = self.0;
         forget(self);
-        LoadedTrapStack(scratch)
+        LoadedTrapStack(scratch, handler)
+    }
+
+    /// 栈的最大用量。
+    ///
+    /// 只对 `new_painted` 构造的陷入栈有意义。
+    #[inline]
+    pub fn high_water_mark(&self) -> usize {
+        unsafe { self.0.as_ref() }.high_water_mark()
     }
 }
 
@@ -84,6 +167,14 @@ impl LoadedTrapStack {
         self.0
     }
 
+    /// 栈的最大用量。
+    ///
+    /// 只对 `new_painted` 构造的陷入栈有意义。
+    #[inline]
+    pub fn high_water_mark(&self) -> usize {
+        unsafe { self.1.as_ref() }.high_water_mark()
+    }
+
     /// 卸载陷入栈。
     #[inline]
     pub fn unload(self) -> FreeTrapStack {
@@ -149,12 +240,78 @@ struct TrapHandler {
     ///
     /// 保存它以提供内存块的范围，同时用于控制内存块的生命周期。
     block: NonNull<dyn TrapStackBlock>,
+    /// 嵌套守卫。
+    ///
+    /// 进入陷入处理后，突发寄存器指向这里，以识别嵌套的陷入。
+    nest: NestGuard,
+    /// 恢复时写回突发寄存器的值。
+    ///
+    /// 第一级陷入时是陷入处理器上下文自身，嵌套陷入时是外层的嵌套守卫。
+    exit_scratch: usize,
+    /// 嵌套级别。
+    level: usize,
+    /// 栈底。
+    ///
+    /// 嵌套陷入时用于检查栈溢出。
+    bottom: usize,
+    /// 应急栈的栈顶。
+    ///
+    /// 发生双重故障时切换到这个栈。为 0 表示没有应急栈。
+    emergency: usize,
+    /// 双重故障处理函数。
+    double_fault: Option<DoubleFaultHandler>,
+    /// 完成处理函数。
+    ///
+    /// `FastContext::call_with` 启动的上下文返回时调用。
+    completion: Option<FastHandler>,
+    /// 延迟工作队列。
+    ///
+    /// 只在第一级的陷入处理器上下文中有效，嵌套陷入也使用第一级的队列。
+    deferred: DeferredQueue,
+    /// 陷入事件缓冲区。
+    ///
+    /// 只在第一级的陷入处理器上下文中有效。
+    #[cfg(feature = "trace")]
+    trace: Option<&'static TraceBuffer>,
+    /// 陷入统计。
+    ///
+    /// 只在第一级的陷入处理器上下文中有效。
+    #[cfg(feature = "trace")]
+    stats: Option<&'static TrapStats>,
     /// 禁止移动标记。
     ///
     /// `TrapHandler` 是放在其内部定义的 `block` 块里的，这是一种自引用结构，不能移动。
     pinned: PhantomPinned,
 }
 
+/// 嵌套守卫在陷入处理器上下文中的偏移。
+const NEST_GUARD: usize = 5 * size_of::<usize>();
+
+/// 嵌套守卫。
+///
+/// 汇编以相同的偏移访问陷入处理器上下文和嵌套守卫：
+/// 首字为 0 表示陷入发生在陷入处理中，第 3 个字用于暂存 a0。
+#[repr(C)]
+struct NestGuard {
+    /// 总是 0。
+    zero: usize,
+    /// 暂存 t0。
+    t0: usize,
+    /// 暂存 a0。
+    a0: usize,
+    /// 暂存 t1。
+    t1: usize,
+}
+
+impl NestGuard {
+    const ZERO: Self = Self {
+        zero: 0,
+        t0: 0,
+        a0: 0,
+        t1: 0,
+    };
+}
+
 impl TrapHandler {
     /// 内存块地址范围。
     #[inline]
@@ -163,6 +320,38 @@ impl TrapHandler {
         block.start as _..block.end as _
     }
 
+    /// 找到第一级的陷入处理器上下文。
+    ///
+    /// 外层可能正在使用它，所以只返回指针。
+    #[inline]
+    fn root(&self) -> *mut TrapHandler {
+        let mut handler = self as *const _ as *mut TrapHandler;
+        // 嵌套陷入时，恢复时写回突发寄存器的值是外层的嵌套守卫
+        while unsafe { (*handler).level } > 1 {
+            handler = unsafe { (*handler).exit_scratch - NEST_GUARD } as *mut TrapHandler;
+        }
+        handler
+    }
+
+    /// 以固定的字节填充栈空间。
+    #[inline]
+    fn paint(&self) {
+        let top = self as *const _ as usize;
+        unsafe { write_bytes(self.bottom as *mut u8, STACK_PAINT, top - self.bottom) };
+    }
+
+    /// 从栈底找到第一个被改写的字节，计算栈的最大用量。
+    ///
+    /// 快速路径消息放在栈底，如果使用过，会被计入用量。
+    fn high_water_mark(&self) -> usize {
+        let top = self as *const _ as usize;
+        let stack = unsafe { slice::from_raw_parts(self.bottom as *const u8, top - self.bottom) };
+        stack
+            .iter()
+            .position(|b| *b != STACK_PAINT)
+            .map_or(0, |i| stack.len() - i)
+    }
+
     /// 如果从快速路径向完整路径转移，可以把一个对象放在栈底。
     /// 用这个方法找到栈底的一个对齐的位置。
     #[inline]
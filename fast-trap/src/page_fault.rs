@@ -0,0 +1,134 @@
+use crate::{
+    read_cause, read_tval, trapped_from_user, EntireContext, EntireContextSeparated, EntireResult,
+    FastContext, FastResult, FlowContext,
+};
+use core::arch::asm;
+
+/// 引起页异常的访问类型。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccessKind {
+    /// 取指。
+    Fetch,
+    /// 读。
+    Load,
+    /// 写或原子操作。
+    Store,
+}
+
+/// 译码的页异常。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PageFault {
+    /// 引起异常的虚地址。
+    pub addr: usize,
+    /// 访问类型。
+    pub access: AccessKind,
+    /// 异常发生在用户态。
+    pub from_user: bool,
+}
+
+impl PageFault {
+    /// 从陷入原因、附加信息和之前的特权级译码当前陷入。
+    ///
+    /// 不是页异常则返回 `None`。
+    #[inline]
+    pub fn from_trap() -> Option<Self> {
+        const FETCH_PAGE_FAULT: usize = 12;
+        const LOAD_PAGE_FAULT: usize = 13;
+        const STORE_PAGE_FAULT: usize = 15;
+        let access = match read_cause() {
+            FETCH_PAGE_FAULT => AccessKind::Fetch,
+            LOAD_PAGE_FAULT => AccessKind::Load,
+            STORE_PAGE_FAULT => AccessKind::Store,
+            _ => return None,
+        };
+        Some(Self {
+            addr: read_tval(),
+            access,
+            from_user: trapped_from_user(),
+        })
+    }
+}
+
+/// 页异常处理结果。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PageFaultResult {
+    /// 已建立映射，刷新这一页的地址转换缓存后重新执行引起异常的指令。
+    Resolved,
+    /// 不需要刷新，直接重新执行引起异常的指令。
+    ///
+    /// 例如映射已经由其他硬件线程建立。
+    Retry,
+    /// 无法处理，需要终止发生异常的控制流。
+    Kill,
+}
+
+/// 页异常处理器。
+///
+/// 按需分页、写时复制和栈增长都可以实现为页异常处理器，不需要访问控制状态寄存器。
+pub trait PageFaultHandler {
+    /// 处理页异常 `fault`，`ctx` 是发生异常的控制流上下文。
+    fn handle(&mut self, fault: &PageFault, ctx: &mut FlowContext) -> PageFaultResult;
+
+    /// 终止发生页异常 `fault` 的控制流。
+    ///
+    /// `handle` 返回 [`PageFaultResult::Kill`] 后调用。
+    fn kill(&mut self, fault: &PageFault, ctx: EntireContextSeparated) -> EntireResult;
+}
+
+impl FastContext {
+    /// 转到完整路径处理当前的页异常。
+    ///
+    /// 不是页异常则交还上下文。
+    ///
+    /// > **NOTICE** 必须先手工调用 `save_args`，或通过其他方式设置参数寄存器。
+    #[inline]
+    pub fn handle_page_fault<H: PageFaultHandler>(
+        self,
+        handler: &'static mut H,
+    ) -> Result<FastResult, Self> {
+        match PageFault::from_trap() {
+            Some(fault) => Ok(self.continue_with(page_fault::<H>, (handler, fault))),
+            None => Err(self),
+        }
+    }
+}
+
+impl EntireContextSeparated {
+    /// 在完整路径处理当前的页异常。
+    ///
+    /// 不是页异常则返回 `None`。返回 [`PageFaultResult::Resolved`] 时已经刷新了地址转换缓存。
+    #[inline]
+    pub fn handle_page_fault(
+        &mut self,
+        handler: &mut impl PageFaultHandler,
+    ) -> Option<PageFaultResult> {
+        let fault = PageFault::from_trap()?;
+        Some(resolve(handler, &fault, self.regs()))
+    }
+}
+
+/// 调用页异常处理器，必要时刷新地址转换缓存。
+#[inline]
+fn resolve(
+    handler: &mut impl PageFaultHandler,
+    fault: &PageFault,
+    ctx: &mut FlowContext,
+) -> PageFaultResult {
+    let result = handler.handle(fault, ctx);
+    if result == PageFaultResult::Resolved {
+        unsafe { asm!("sfence.vma {}, zero", in(reg) fault.addr) };
+    }
+    result
+}
+
+/// 在完整路径调用页异常处理器。
+extern "C" fn page_fault<H: PageFaultHandler>(
+    ctx: EntireContext<(&'static mut H, PageFault)>,
+) -> EntireResult {
+    let (mut ctx, mail) = ctx.split();
+    let (handler, fault) = mail.get();
+    match resolve(handler, &fault, ctx.regs()) {
+        PageFaultResult::Resolved | PageFaultResult::Retry => ctx.restore(),
+        PageFaultResult::Kill => handler.kill(&fault, ctx),
+    }
+}
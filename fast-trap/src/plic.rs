@@ -0,0 +1,167 @@
+use crate::{EntireContext, EntireResult, FastContext, FastResult, IrqController};
+use core::ptr::NonNull;
+
+/// 平台级中断控制器的一个上下文。
+///
+/// 每个硬件线程的每个特权级是一个上下文，qemu `virt` 上第 `n` 个硬件线程的 M 态是 `2n`，S 态是 `2n+1`。
+pub struct Plic {
+    base: usize,
+    context: usize,
+}
+
+impl Plic {
+    /// 基地址为 `base` 的 PLIC 的第 `context` 个上下文。
+    ///
+    /// # Safety
+    ///
+    /// `base` 必须是 PLIC 的基地址。
+    #[inline]
+    pub const unsafe fn new(base: usize, context: usize) -> Self {
+        Self { base, context }
+    }
+
+    /// 设置中断源 `irq` 的优先级，0 表示不会触发。
+    #[inline]
+    pub fn set_priority(&self, irq: usize, priority: u32) {
+        unsafe { self.reg(irq * 4).write_volatile(priority) }
+    }
+
+    /// 设置这个上下文的优先级阈值，只有优先级高于阈值的中断会触发。
+    #[inline]
+    pub fn set_threshold(&self, threshold: u32) {
+        unsafe {
+            self.reg(0x20_0000 + self.context * 0x1000)
+                .write_volatile(threshold)
+        }
+    }
+
+    /// 为这个上下文打开中断源 `irq`。
+    #[inline]
+    pub fn enable(&self, irq: usize) {
+        let reg = self.enable_reg(irq);
+        unsafe { reg.write_volatile(reg.read_volatile() | 1 << (irq % 32)) }
+    }
+
+    /// 为这个上下文关闭中断源 `irq`。
+    #[inline]
+    pub fn disable(&self, irq: usize) {
+        let reg = self.enable_reg(irq);
+        unsafe { reg.write_volatile(reg.read_volatile() & !(1 << (irq % 32))) }
+    }
+
+    #[inline]
+    fn enable_reg(&self, irq: usize) -> *mut u32 {
+        self.reg(0x2000 + self.context * 0x80 + irq / 32 * 4)
+    }
+
+    #[inline]
+    fn claim_reg(&self) -> *mut u32 {
+        self.reg(0x20_0004 + self.context * 0x1000)
+    }
+
+    #[inline]
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as _
+    }
+}
+
+impl IrqController for Plic {
+    #[inline]
+    fn claim(&mut self) -> Option<usize> {
+        match unsafe { self.claim_reg().read_volatile() } {
+            0 => None,
+            irq => Some(irq as _),
+        }
+    }
+
+    #[inline]
+    fn complete(&mut self, irq: usize) {
+        unsafe { self.claim_reg().write_volatile(irq as _) }
+    }
+}
+
+/// 设备中断处理函数，参数是中断源编号。
+#[derive(Clone, Copy)]
+pub enum IrqHandler {
+    /// 在快速路径中关中断地调用，只应该做轻量的操作。
+    Fast(fn(usize)),
+    /// 转到完整路径调用。
+    Entire(fn(usize)),
+}
+
+/// 外部中断分发表。
+///
+/// 认领中断，按中断源编号调用设备注册的处理函数，处理完成后自动通知中断控制器。
+/// 没有注册处理函数或编号超出 `N` 的中断被认领后直接完成。
+pub struct IrqDispatcher<C, const N: usize> {
+    controller: C,
+    handlers: [Option<IrqHandler>; N],
+}
+
+impl<C: IrqController, const N: usize> IrqDispatcher<C, N> {
+    /// 构造空的分发表。
+    #[inline]
+    pub const fn new(controller: C) -> Self {
+        Self {
+            controller,
+            handlers: [None; N],
+        }
+    }
+
+    /// 中断控制器。
+    #[inline]
+    pub fn controller(&mut self) -> &mut C {
+        &mut self.controller
+    }
+
+    /// 为中断源 `irq` 注册 `handler`，替换之前注册的。
+    ///
+    /// 在外部中断之外修改分发表时，必须屏蔽外部中断。
+    #[inline]
+    pub fn register(&mut self, irq: usize, handler: IrqHandler) {
+        self.handlers[irq] = Some(handler);
+    }
+
+    /// 注销中断源 `irq` 的处理函数。
+    #[inline]
+    pub fn unregister(&mut self, irq: usize) {
+        self.handlers[irq] = None;
+    }
+
+    /// 在快速路径中分发外部中断。
+    ///
+    /// 依次认领挂起的中断并调用快速处理函数。
+    /// 遇到需要在完整路径中处理的中断时，带着它转到完整路径，其余挂起的中断会再次触发。
+    ///
+    /// > **NOTICE** 必须先手工调用 `save_args`，或通过其他方式设置参数寄存器。
+    pub fn dispatch(&mut self, ctx: FastContext) -> FastResult
+    where
+        Self: 'static,
+    {
+        while let Some(irq) = self.controller.claim() {
+            match self.handlers.get(irq).copied().flatten() {
+                Some(IrqHandler::Fast(f)) => f(irq),
+                Some(IrqHandler::Entire(_)) => {
+                    return ctx.continue_with(dispatch_entire::<C, N>, (NonNull::from(self), irq));
+                }
+                None => {}
+            }
+            self.controller.complete(irq);
+        }
+        ctx.restore()
+    }
+}
+
+/// 在完整路径中调用设备的处理函数，然后完成中断。
+extern "C" fn dispatch_entire<C: IrqController, const N: usize>(
+    ctx: EntireContext<(NonNull<IrqDispatcher<C, N>>, usize)>,
+) -> EntireResult {
+    let (ctx, mail) = ctx.split();
+    let (mut dispatcher, irq) = mail.get();
+    let dispatcher = unsafe { dispatcher.as_mut() };
+    if let Some(IrqHandler::Entire(f)) = dispatcher.handlers[irq] {
+        f(irq);
+    }
+    dispatcher.controller.complete(irq);
+    ctx.restore()
+}
@@ -0,0 +1,305 @@
+use crate::{
+    enable_timer_interrupt, read_cause, read_scratch, restore_timer_interrupt,
+    return_with_interrupt, soft_trap_with, EntireContext, EntireResult, FastContext, FastResult,
+    FlowContext, FreeTrapStack, IllegalStack, TrapHandler, TrapStackBlock, TIMER_INTERRUPT,
+};
+use core::{mem::replace, ptr::NonNull};
+
+/// 抢占策略。
+///
+/// 提供定时器，并决定被抢占后恢复哪个控制流。
+pub trait Preemption {
+    /// 在 `budget` 个计时周期后触发时钟中断。
+    fn arm(&mut self, budget: u64);
+
+    /// 取消定时，并清除已经挂起的时钟中断。
+    fn disarm(&mut self);
+
+    /// 可抢占的调用被抢占。
+    ///
+    /// 返回要恢复的控制流。默认直接恢复被抢占的调用。
+    #[inline]
+    fn preempted(&mut self, preempted: Preempted) -> Preempted {
+        preempted
+    }
+
+    /// 由 [`Preempted::with_exit`] 构造的控制流返回了 `ret`。
+    ///
+    /// 返回要恢复的控制流。默认认为不会发生。
+    #[inline]
+    fn exited(&mut self, ret: usize) -> Preempted {
+        panic!("flow exited with {ret:#x}")
+    }
+
+    /// 可抢占调用中发生了时钟中断以外的陷入，或者发生了嵌套陷入。
+    ///
+    /// 在快速路径中调用，参数寄存器已经保存，处理方式同快速路径函数。默认直接恢复。
+    /// 嵌套陷入（`ctx.nesting_level() > 1`）包括时钟中断都交给这里，外层的陷入处理可能正在使用抢占策略。
+    #[inline]
+    fn trapped(&mut self, ctx: FastContext) -> FastResult {
+        ctx.restore()
+    }
+}
+
+/// 被抢占的控制流。
+///
+/// 保存了被抢占时的全部寄存器，从 `Preemption::preempted` 返回它即可恢复执行。
+pub struct Preempted(FlowContext);
+
+impl Preempted {
+    /// 构造一个新的控制流，它在 `stack` 上以 `arg` 为参数执行 `entry`。
+    ///
+    /// gp 和 tp 取自当前控制流。
+    pub fn new(entry: extern "C" fn(usize) -> !, arg: usize, stack: &mut [u8]) -> Self {
+        let mut context = FlowContext::ZERO;
+        context.a[0] = arg;
+        context.sp = stack.as_mut_ptr_range().end as usize & !15;
+        context.pc = entry as usize;
+        context.inherit_others();
+        Self(context)
+    }
+
+    /// 构造一个新的控制流，它在 `stack` 上以 `arg` 为参数执行 `entry`。
+    ///
+    /// `entry` 返回时控制流结束，返回值交给 `Preemption::exited`。
+    pub fn with_exit(entry: extern "C" fn(usize) -> usize, arg: usize, stack: &mut [u8]) -> Self {
+        let mut ans = Self::new(flow_exit, 0, stack);
+        ans.0.ra = ans.0.pc;
+        ans.0.pc = entry as usize;
+        ans.0.a[0] = arg;
+        ans
+    }
+
+    /// 被抢占时的控制流上下文。
+    #[inline]
+    pub fn context(&self) -> &FlowContext {
+        &self.0
+    }
+
+    /// 丢弃当前控制流，切换到这个控制流。
+    ///
+    /// # Safety
+    ///
+    /// 只能在 [`preemptible`] 调用中使用。当前控制流的栈不会再被访问。
+    pub unsafe fn resume(self) -> ! {
+        let switch = Switch {
+            target: self.0,
+            save: None,
+        };
+        request(Request::Switch(&switch));
+        unreachable!()
+    }
+
+    /// 挂起当前控制流并保存到 `save`，然后切换到这个控制流。
+    ///
+    /// 从 `save` 恢复当前控制流时返回。
+    ///
+    /// # Safety
+    ///
+    /// 只能在 [`preemptible`] 调用中使用。
+    pub unsafe fn yield_to(self, save: &mut Option<Preempted>) {
+        let switch = Switch {
+            target: self.0,
+            save: Some(NonNull::from(save)),
+        };
+        request(Request::Switch(&switch));
+    }
+}
+
+/// 主动让出当前控制流。
+///
+/// 如同被时钟中断抢占，由 `Preemption::preempted` 决定接下来恢复哪个控制流。
+/// 不在 [`preemptible`] 调用中时什么也不做。
+#[inline]
+pub fn yield_now() {
+    unsafe { request(Request::Yield) };
+}
+
+/// 控制流返回时进入这里，结束控制流。
+extern "C" fn flow_exit(ret: usize) -> ! {
+    unsafe { request(Request::Exit(ret)) };
+    unreachable!()
+}
+
+/// 控制流向可抢占调用发起的请求。
+///
+/// 陷入原因寄存器只能保存实现支持的原因，所以请求写在陷入上下文中，再模拟一个陷入。
+#[derive(Clone, Copy)]
+enum Request {
+    /// 主动让出。
+    Yield,
+    /// 控制流结束，返回了这个值。
+    Exit(usize),
+    /// 切换控制流。
+    Switch(*const Switch),
+}
+
+/// 向当前加载的可抢占调用的陷入栈发起请求。
+///
+/// 没有加载陷入栈、正在陷入处理中或者当前加载的不是可抢占调用的陷入栈时什么也不做。
+///
+/// # Safety
+///
+/// 如同发生一个陷入。
+unsafe fn request(req: Request) {
+    let scratch = read_scratch();
+    // 陷入处理中突发寄存器指向嵌套守卫，它的首字为 0，而陷入处理器上下文的首字是上下文指针
+    if scratch == 0 || *(scratch as *const usize) == 0 {
+        return;
+    }
+    let handler = &*(scratch as *const TrapHandler);
+    if handler.fast_handler as usize != fast_handler as usize {
+        return;
+    }
+    let frame = handler.context.as_ptr().cast::<PreemptFrame>();
+    soft_trap_with(|| (*frame).request = Some(req));
+}
+
+/// 切换控制流的请求。
+///
+/// 放在发起切换的控制流的栈上，通过陷入上下文传递给陷入处理函数。
+struct Switch {
+    target: FlowContext,
+    save: Option<NonNull<Option<Preempted>>>,
+}
+
+/// 在时间预算内可抢占地调用 `f`。
+///
+/// 在 `stack` 上构造并加载一个陷入栈，打开时钟中断，然后调用 `f`。
+/// 每当 `budget` 耗尽，就从时钟中断中把当前控制流挂起为 [`Preempted`]，
+/// 交给 `preemption` 决定接下来恢复哪个控制流，并为它重新设置时间预算。
+///
+/// 调用期间发生的其他陷入交给 `Preemption::trapped` 处理。
+pub fn preemptible<T>(
+    stack: impl TrapStackBlock,
+    budget: u64,
+    preemption: &mut dyn Preemption,
+    f: impl FnOnce() -> T,
+) -> Result<T, IllegalStack> {
+    let mut frame = PreemptFrame {
+        context: FlowContext::ZERO,
+        preemption,
+        budget,
+        request: None,
+    };
+    let frame = NonNull::from(&mut frame);
+    let loaded = FreeTrapStack::new(stack, frame.cast(), fast_handler)?.load();
+    let frame = frame.as_ptr();
+    unsafe {
+        (*(*frame).preemption).arm(budget);
+        let state = enable_timer_interrupt();
+        let ans = f();
+        restore_timer_interrupt(state);
+        (*(*frame).preemption).disarm();
+        drop(loaded);
+        Ok(ans)
+    }
+}
+
+/// 可抢占调用的陷入上下文。
+///
+/// 控制流上下文必须放在开头，陷入处理函数从上下文指针找到整个结构。
+/// 陷入栈在 [`preemptible`] 返回前卸载，陷入处理函数不会在 `'a` 之外访问抢占策略。
+#[repr(C)]
+struct PreemptFrame<'a> {
+    context: FlowContext,
+    preemption: *mut (dyn Preemption + 'a),
+    budget: u64,
+    /// 尚未处理的请求。
+    request: Option<Request>,
+}
+
+impl<'a> PreemptFrame<'a> {
+    /// 从控制流上下文找到整个结构。
+    #[inline]
+    unsafe fn from_context(ctx: &mut FlowContext) -> &mut Self {
+        &mut *(ctx as *mut FlowContext).cast()
+    }
+}
+
+/// 快速路径只取消定时，挂起、结束、切换和恢复在完整路径中进行。
+///
+/// 只有第一级陷入的控制流上下文是 [`PreemptFrame`]，嵌套陷入直接交给 `Preemption::trapped`。
+extern "C" fn fast_handler(
+    mut ctx: FastContext,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> FastResult {
+    ctx.save_args(a1, a2, a3, a4, a5, a6, a7);
+    if ctx.nesting_level() > 1 {
+        let frame = ctx.root_context().cast::<PreemptFrame>().as_ptr();
+        return unsafe { (*(*frame).preemption).trapped(ctx) };
+    }
+    let frame = unsafe { PreemptFrame::from_context(ctx.regs()) };
+    let (request, preemption) = (frame.request.take(), frame.preemption);
+    match request {
+        Some(Request::Yield) => {
+            unsafe { (*preemption).disarm() };
+            ctx.continue_with(preempt, ())
+        }
+        Some(Request::Exit(ret)) => ctx.continue_with(exit, ret),
+        Some(Request::Switch(req)) => ctx.continue_with(switch, req),
+        None => match read_cause() {
+            TIMER_INTERRUPT => {
+                unsafe { (*preemption).disarm() };
+                ctx.continue_with(preempt, ())
+            }
+            _ => unsafe { (*preemption).trapped(ctx) },
+        },
+    }
+}
+
+/// 挂起被抢占或主动让出的控制流，恢复 `Preemption::preempted` 选择的控制流。
+extern "C" fn preempt(ctx: EntireContext) -> EntireResult {
+    let (mut ctx, _) = ctx.split();
+    let frame = unsafe { PreemptFrame::from_context(ctx.regs()) };
+    let preemption = unsafe { &mut *frame.preemption };
+    unsafe { frame.context.save_others() };
+    let preempted = Preempted(replace(&mut frame.context, FlowContext::ZERO));
+    frame.context = preemption.preempted(preempted).0;
+    unsafe {
+        frame.context.load_others();
+        return_with_interrupt();
+    }
+    preemption.arm(frame.budget);
+    ctx.restore()
+}
+
+/// 丢弃结束的控制流，恢复 `Preemption::exited` 选择的控制流。
+extern "C" fn exit(ctx: EntireContext<usize>) -> EntireResult {
+    let (mut ctx, ret) = ctx.split();
+    let frame = unsafe { PreemptFrame::from_context(ctx.regs()) };
+    let preemption = unsafe { &mut *frame.preemption };
+    frame.context = preemption.exited(ret.get()).0;
+    unsafe {
+        frame.context.load_others();
+        return_with_interrupt();
+    }
+    preemption.arm(frame.budget);
+    ctx.restore()
+}
+
+/// 按请求切换控制流。
+extern "C" fn switch(ctx: EntireContext<*const Switch>) -> EntireResult {
+    let (mut ctx, switch) = ctx.split();
+    let frame = unsafe { PreemptFrame::from_context(ctx.regs()) };
+    let Switch { target, save } = unsafe { switch.get().read() };
+    match save {
+        Some(mut save) => unsafe {
+            frame.context.save_others();
+            *save.as_mut() = Some(Preempted(replace(&mut frame.context, target)));
+        },
+        None => frame.context = target,
+    }
+    unsafe {
+        frame.context.load_others();
+        return_with_interrupt();
+        (*frame.preemption).arm(frame.budget);
+    }
+    ctx.restore()
+}
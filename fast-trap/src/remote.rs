@@ -0,0 +1,145 @@
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// 发往其他硬件线程的请求。
+#[derive(Clone, Copy, Debug)]
+pub enum Request {
+    /// 重新调度。
+    Reschedule,
+    /// 停止运行。
+    Stop,
+    /// 在目标的快速路径中调用 `f(arg)`。
+    Run(fn(usize), usize),
+}
+
+/// 核间中断。
+pub trait Ipi {
+    /// 向第 `hart` 个硬件线程发送核间中断。
+    fn send(&self, hart: usize);
+
+    /// 清除第 `hart` 个硬件线程的核间中断，由目标硬件线程自己调用。
+    fn clear(&self, hart: usize);
+}
+
+/// ACLINT MSWI 或 CLINT 的 `msip` 寄存器。
+pub struct Mswi(usize);
+
+impl Mswi {
+    /// 基地址为 `base` 的 MSWI 设备。
+    ///
+    /// # Safety
+    ///
+    /// `base` 必须是 ACLINT MSWI 或 CLINT 的基地址。
+    #[inline]
+    pub const unsafe fn new(base: usize) -> Self {
+        Self(base)
+    }
+}
+
+impl Ipi for Mswi {
+    #[inline]
+    fn send(&self, hart: usize) {
+        unsafe { ((self.0 + hart * 4) as *mut u32).write_volatile(1) }
+    }
+
+    #[inline]
+    fn clear(&self, hart: usize) {
+        unsafe { ((self.0 + hart * 4) as *mut u32).write_volatile(0) }
+    }
+}
+
+/// ACLINT SSWI 设备。
+///
+/// 写 `setssip` 寄存器置起目标的 `sip.SSIP`，目标自己清除这一位。
+#[cfg(feature = "riscv-s")]
+pub struct Sswi(usize);
+
+#[cfg(feature = "riscv-s")]
+impl Sswi {
+    /// 基地址为 `base` 的 SSWI 设备。
+    ///
+    /// # Safety
+    ///
+    /// `base` 必须是 ACLINT SSWI 的基地址。
+    #[inline]
+    pub const unsafe fn new(base: usize) -> Self {
+        Self(base)
+    }
+}
+
+#[cfg(feature = "riscv-s")]
+impl Ipi for Sswi {
+    #[inline]
+    fn send(&self, hart: usize) {
+        unsafe { ((self.0 + hart * 4) as *mut u32).write_volatile(1) }
+    }
+
+    #[inline]
+    fn clear(&self, _hart: usize) {
+        unsafe { core::arch::asm!("csrci sip, {}", const 1 << 1) };
+    }
+}
+
+/// 每个硬件线程一个的请求邮箱。
+///
+/// 邮箱只能容纳一个请求，发送者可以有多个，只有目标硬件线程自己接收。
+pub struct Mailbox {
+    state: AtomicUsize,
+    request: UnsafeCell<MaybeUninit<Request>>,
+}
+
+unsafe impl Sync for Mailbox {}
+
+impl Mailbox {
+    const EMPTY: usize = 0;
+    const WRITING: usize = 1;
+    const FULL: usize = 2;
+
+    /// 构造空的邮箱。
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(Self::EMPTY),
+            request: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// 把 `request` 放进第 `hart` 个硬件线程的邮箱，然后通过 `ipi` 通知它。
+    ///
+    /// 邮箱中已有请求时返回 `Err`。
+    pub fn send(&self, request: Request, ipi: &impl Ipi, hart: usize) -> Result<(), Request> {
+        if self
+            .state
+            .compare_exchange(
+                Self::EMPTY,
+                Self::WRITING,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            return Err(request);
+        }
+        unsafe { (*self.request.get()).write(request) };
+        self.state.store(Self::FULL, Ordering::Release);
+        ipi.send(hart);
+        Ok(())
+    }
+
+    /// 在第 `hart` 个硬件线程的快速路径中清除核间中断，取出请求。
+    ///
+    /// 先清除中断再取请求，取出之后到达的请求会再次触发中断。
+    #[inline]
+    pub fn receive(&self, ipi: &impl Ipi, hart: usize) -> Option<Request> {
+        ipi.clear(hart);
+        if self.state.load(Ordering::Acquire) != Self::FULL {
+            return None;
+        }
+        let request = unsafe { (*self.request.get()).assume_init_read() };
+        self.state.store(Self::EMPTY, Ordering::Release);
+        Some(request)
+    }
+}
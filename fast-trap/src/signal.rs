@@ -0,0 +1,116 @@
+use crate::{copy_from_user, copy_to_user, FlowContext, UserFault};
+use core::{
+    mem::{size_of, MaybeUninit},
+    ptr::{self, addr_of, addr_of_mut},
+    slice,
+};
+
+/// 信号帧。
+///
+/// 投递信号时压入用户栈，信号处理函数返回时由 `sigreturn` 取回。
+#[repr(C)]
+pub struct SignalFrame<T> {
+    /// 被信号打断的控制流上下文。
+    pub context: FlowContext,
+    /// 随信号传递的附加信息，例如信号编号或浮点上下文。
+    pub info: T,
+}
+
+/// 可以随信号传递的附加信息。
+///
+/// 附加信息以字节的形式写入用户栈，再从用户可以任意修改的内存中读回。
+///
+/// # Safety
+///
+/// 实现这个特质的类型不能有填充字节，并且任意位模式都是它的合法值。
+pub unsafe trait SignalInfo: Copy {}
+
+macro_rules! impl_signal_info {
+    ($($ty:ty)*) => {
+        $(unsafe impl SignalInfo for $ty {})*
+    };
+}
+
+impl_signal_info!(u8 u16 u32 u64 usize i8 i16 i32 i64 isize);
+
+unsafe impl<T: SignalInfo, const N: usize> SignalInfo for [T; N] {}
+
+/// 以字节的形式访问 `val`。
+///
+/// # Safety
+///
+/// `T` 不能有填充字节。
+#[inline]
+unsafe fn bytes_of<T>(val: &T) -> &[u8] {
+    slice::from_raw_parts(val as *const T as *const u8, size_of::<T>())
+}
+
+impl FlowContext {
+    /// 向这个控制流投递信号。
+    ///
+    /// 把当前上下文和 `sigframe` 组成的 [`SignalFrame`] 压入用户栈，
+    /// 然后令控制流从 `handler_pc` 开始执行信号处理函数，`a0` 和 `sp` 都指向信号帧。
+    /// 信号处理函数结束时应该以投递时的 `sp` 发起 `sigreturn` 系统调用，
+    /// 内核在其中调用 [`FlowContext::sigreturn`]。
+    ///
+    /// 用户栈无效，包括 `sp` 太小放不下信号帧时，返回 [`UserFault`]。
+    ///
+    /// > **NOTICE** `pc` 必须是控制流被打断的位置。
+    /// > 框架只保存整数上下文，浮点上下文由调用者放在 `sigframe` 中。
+    /// >
+    /// > 必须在完整路径中投递：快速路径不保存 s1-s11，上下文中的 s 寄存器不是现场的值。
+    ///
+    /// # Safety
+    ///
+    /// 这个控制流的 `sp` 必须在用户地址空间中，写信号帧时发生的访存异常需要由陷入处理函数修复。
+    pub unsafe fn deliver_signal<T: SignalInfo>(
+        &mut self,
+        handler_pc: usize,
+        sigframe: T,
+    ) -> Result<(), UserFault> {
+        let size = size_of::<SignalFrame<T>>();
+        let sp = self.sp.checked_sub(size).ok_or(UserFault)? & !15;
+        let frame = SignalFrame {
+            context: ptr::read(self),
+            info: sigframe,
+        };
+        // 分别写上下文和附加信息，不读写它们之间和之后的填充字节
+        let info = addr_of!(frame.info) as usize - addr_of!(frame) as usize;
+        copy_to_user(sp as _, bytes_of(&frame.context))?;
+        copy_to_user((sp + info) as _, bytes_of(&frame.info))?;
+        self.sp = sp;
+        self.pc = handler_pc;
+        self.a[0] = sp;
+        Ok(())
+    }
+
+    /// 从 `sp` 指向的信号帧恢复被信号打断的上下文，返回投递时的附加信息。
+    ///
+    /// 信号帧在用户内存中，可能已经被修改，调用者不能信任恢复的内容。
+    ///
+    /// # Safety
+    ///
+    /// 这个控制流的 `sp` 必须在用户地址空间中，读信号帧时发生的访存异常需要由陷入处理函数修复。
+    pub unsafe fn sigreturn<T: SignalInfo>(&mut self) -> Result<T, UserFault> {
+        let mut frame = MaybeUninit::<SignalFrame<T>>::uninit();
+        let base = frame.as_mut_ptr();
+        let context = addr_of_mut!((*base).context);
+        let info = addr_of_mut!((*base).info);
+        let info_sp = self
+            .sp
+            .checked_add(info as usize - base as usize)
+            .ok_or(UserFault)?;
+        copy_from_user(
+            slice::from_raw_parts_mut(context as *mut u8, size_of::<FlowContext>()),
+            self.sp as _,
+        )?;
+        copy_from_user(
+            slice::from_raw_parts_mut(info as *mut u8, size_of::<T>()),
+            info_sp as _,
+        )?;
+        // 上下文只有整数，附加信息的任意位模式都合法
+        let frame = frame.assume_init();
+        *self = frame.context;
+        Ok(frame.info)
+    }
+}
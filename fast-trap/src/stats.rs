@@ -0,0 +1,205 @@
+use crate::{FastResult, FreeTrapStack};
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+/// 分别统计的异常和中断原因数。
+///
+/// 编号不小于这个值的原因合并统计。
+pub const CAUSE_SLOTS: usize = 32;
+
+/// 周期直方图的桶数。
+///
+/// 第 0 个桶统计 0 个周期，第 `i` 个桶统计 `[2^(i-1), 2^i)` 个周期，最后一个桶没有上界。
+pub const HISTOGRAM_BUCKETS: usize = 32;
+
+const INTERRUPT: usize = 1 << (usize::BITS - 1);
+
+/// 一种陷入原因的计数。
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct CauseStats {
+    /// 陷入的次数。
+    pub total: usize,
+    /// 快速路径以 [`FastResult::Continue`] 返回，转到完整路径的次数。
+    pub continued: usize,
+}
+
+impl CauseStats {
+    /// 在快速路径中处理完的次数。
+    ///
+    /// 两个计数不是同时读取的，读取时正在累加或清零可能使 `continued` 大于 `total`。
+    #[inline]
+    pub fn fast(&self) -> usize {
+        self.total.saturating_sub(self.continued)
+    }
+}
+
+/// 陷入统计。
+///
+/// 在快速路径返回时累加，比 [`TraceBuffer`](crate::TraceBuffer) 廉价，不会丢失事件。
+/// 按陷入原因计数，并把快速路径用去的周期数按 2 的幂分别计入处理完和转到完整路径的直方图。
+/// 统计的周期数只包括快速路径函数，不包括完整路径。
+///
+/// 每个硬件线程的陷入栈应该使用自己的统计，控制流可以随时读取。
+pub struct TrapStats {
+    exceptions: [CauseCounter; CAUSE_SLOTS],
+    interrupts: [CauseCounter; CAUSE_SLOTS],
+    other: CauseCounter,
+    fast: [AtomicUsize; HISTOGRAM_BUCKETS],
+    continued: [AtomicUsize; HISTOGRAM_BUCKETS],
+}
+
+struct CauseCounter {
+    total: AtomicUsize,
+    continued: AtomicUsize,
+}
+
+impl CauseCounter {
+    /// 只用于初始化数组。
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: Self = Self {
+        total: AtomicUsize::new(0),
+        continued: AtomicUsize::new(0),
+    };
+
+    /// 先于总数读取转到完整路径的次数，累加时总数先增加。
+    #[inline]
+    fn load(&self) -> CauseStats {
+        let continued = self.continued.load(Relaxed);
+        CauseStats {
+            total: self.total.load(Relaxed),
+            continued,
+        }
+    }
+}
+
+impl TrapStats {
+    /// 创建清零的统计。
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicUsize = AtomicUsize::new(0);
+        Self {
+            exceptions: [CauseCounter::ZERO; CAUSE_SLOTS],
+            interrupts: [CauseCounter::ZERO; CAUSE_SLOTS],
+            other: CauseCounter::ZERO,
+            fast: [ZERO; HISTOGRAM_BUCKETS],
+            continued: [ZERO; HISTOGRAM_BUCKETS],
+        }
+    }
+
+    /// 读取陷入原因 `cause` 的计数。
+    ///
+    /// 编号不小于 [`CAUSE_SLOTS`] 的原因返回合并的计数。
+    #[inline]
+    pub fn cause(&self, cause: usize) -> CauseStats {
+        self.counter(cause).load()
+    }
+
+    /// 遍历发生过的陷入原因及其计数，不包括合并统计的原因。
+    pub fn causes(&self) -> impl Iterator<Item = (usize, CauseStats)> + '_ {
+        let exceptions = self.exceptions.iter().enumerate();
+        let interrupts = self.interrupts.iter().enumerate();
+        exceptions
+            .map(|(code, counter)| (code, counter.load()))
+            .chain(interrupts.map(|(code, counter)| (INTERRUPT | code, counter.load())))
+            .filter(|(_, stats)| stats.total > 0)
+    }
+
+    /// 编号不小于 [`CAUSE_SLOTS`] 的陷入原因合并的计数。
+    #[inline]
+    pub fn other(&self) -> CauseStats {
+        self.other.load()
+    }
+
+    /// 所有陷入原因的计数之和。
+    pub fn total(&self) -> CauseStats {
+        self.exceptions
+            .iter()
+            .chain(&self.interrupts)
+            .chain([&self.other])
+            .map(CauseCounter::load)
+            .fold(CauseStats::default(), |sum, stats| CauseStats {
+                total: sum.total + stats.total,
+                continued: sum.continued + stats.continued,
+            })
+    }
+
+    /// 在快速路径中处理完的陷入用去周期数的直方图。
+    #[inline]
+    pub fn fast_histogram(&self) -> [usize; HISTOGRAM_BUCKETS] {
+        load_histogram(&self.fast)
+    }
+
+    /// 转到完整路径的陷入在快速路径中用去周期数的直方图。
+    #[inline]
+    pub fn continued_histogram(&self) -> [usize; HISTOGRAM_BUCKETS] {
+        load_histogram(&self.continued)
+    }
+
+    /// 清零所有统计。
+    ///
+    /// 与陷入并发时，正在累加的陷入可能部分被清零。
+    pub fn reset(&self) {
+        for counter in self
+            .exceptions
+            .iter()
+            .chain(&self.interrupts)
+            .chain([&self.other])
+        {
+            counter.total.store(0, Relaxed);
+            counter.continued.store(0, Relaxed);
+        }
+        for bucket in self.fast.iter().chain(&self.continued) {
+            bucket.store(0, Relaxed);
+        }
+    }
+
+    /// 累加一次陷入。
+    ///
+    /// 嵌套陷入可能打断外层的累加，所以使用原子加法。
+    pub(crate) fn record(&self, cause: usize, result: FastResult, cycles: usize) {
+        let counter = self.counter(cause);
+        counter.total.fetch_add(1, Relaxed);
+        let histogram = if result == FastResult::Continue {
+            counter.continued.fetch_add(1, Relaxed);
+            &self.continued
+        } else {
+            &self.fast
+        };
+        let bucket = (usize::BITS - cycles.leading_zeros()) as usize;
+        histogram[bucket.min(HISTOGRAM_BUCKETS - 1)].fetch_add(1, Relaxed);
+    }
+
+    #[inline]
+    fn counter(&self, cause: usize) -> &CauseCounter {
+        let (slots, code) = if cause & INTERRUPT != 0 {
+            (&self.interrupts, cause & !INTERRUPT)
+        } else {
+            (&self.exceptions, cause)
+        };
+        slots.get(code).unwrap_or(&self.other)
+    }
+}
+
+#[inline]
+fn load_histogram(histogram: &[AtomicUsize; HISTOGRAM_BUCKETS]) -> [usize; HISTOGRAM_BUCKETS] {
+    let mut ans = [0; HISTOGRAM_BUCKETS];
+    for (count, bucket) in ans.iter_mut().zip(histogram) {
+        *count = bucket.load(Relaxed);
+    }
+    ans
+}
+
+impl Default for TrapStats {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FreeTrapStack {
+    /// 把在这个陷入栈上发生的陷入累加到 `stats`。
+    ///
+    /// 嵌套陷入也累加到同一个统计。
+    pub fn set_stats(&mut self, stats: &'static TrapStats) {
+        unsafe { self.0.as_mut() }.stats = Some(stats);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{preemptible, yield_now, IllegalStack, Preempted, Preemption, TrapStackBlock};
+use core::cell::{Cell, UnsafeCell};
+
+/// 线程表。
+///
+/// 以轮转方式调度至多 `N` 个线程，0 号线程是调用 [`Threads::run`] 的控制流。
+/// 线程通过 [`yield_now`] 主动让出，或在时间片耗尽时被抢占。
+pub struct Threads<T, const N: usize> {
+    timer: UnsafeCell<T>,
+    slots: [Cell<Thread>; N],
+    current: Cell<usize>,
+}
+
+/// 线程句柄，用于等待线程结束。
+#[derive(Debug)]
+pub struct JoinHandle(usize);
+
+/// 线程状态。
+#[allow(clippy::large_enum_variant)]
+enum Thread {
+    /// 空闲的槽。
+    Empty,
+    /// 正在运行。
+    Running,
+    /// 可以恢复。
+    Ready(Preempted),
+    /// 已结束，保存返回值。
+    Finished(usize),
+}
+
+impl<T: Preemption, const N: usize> Threads<T, N> {
+    /// 构造线程表。
+    ///
+    /// `timer` 只用于设置和取消定时器，它的 `preempted` 和 `exited` 不会被调用。
+    pub fn new(timer: T) -> Self {
+        assert!(N > 0);
+        Self {
+            timer: UnsafeCell::new(timer),
+            slots: [(); N].map(|_| Cell::new(Thread::Empty)),
+            current: Cell::new(0),
+        }
+    }
+
+    /// 在 `stack` 上构造陷入栈，以 `budget` 为时间片，将 `f` 作为 0 号线程调用。
+    pub fn run<R>(
+        &self,
+        stack: impl TrapStackBlock,
+        budget: u64,
+        f: impl FnOnce() -> R,
+    ) -> Result<R, IllegalStack> {
+        self.current.set(0);
+        self.slots[0].set(Thread::Running);
+        let ans = preemptible(stack, budget, &mut &*self, f);
+        self.slots[0].set(Thread::Empty);
+        ans
+    }
+
+    /// 在 `stack` 上创建线程执行 `entry(arg)`。
+    ///
+    /// 线程表已满时返回 `None`。
+    pub fn spawn(
+        &self,
+        stack: &'static mut [u8],
+        entry: extern "C" fn(usize) -> usize,
+        arg: usize,
+    ) -> Option<JoinHandle> {
+        let i = self
+            .slots
+            .iter()
+            .position(|slot| matches!(self.peek(slot), Peek::Empty))?;
+        self.slots[i].set(Thread::Ready(Preempted::with_exit(entry, arg, stack)));
+        Some(JoinHandle(i))
+    }
+
+    /// 等待线程结束，取得返回值。
+    ///
+    /// 等待时主动让出。
+    pub fn join(&self, handle: JoinHandle) -> usize {
+        let slot = &self.slots[handle.0];
+        loop {
+            match slot.replace(Thread::Empty) {
+                Thread::Finished(ret) => break ret,
+                thread => {
+                    slot.set(thread);
+                    yield_now();
+                }
+            }
+        }
+    }
+
+    /// 查看线程状态。
+    #[inline]
+    fn peek(&self, slot: &Cell<Thread>) -> Peek {
+        let thread = slot.replace(Thread::Empty);
+        let ans = match thread {
+            Thread::Empty => Peek::Empty,
+            Thread::Ready(_) => Peek::Ready,
+            Thread::Running | Thread::Finished(_) => Peek::Other,
+        };
+        slot.set(thread);
+        ans
+    }
+
+    /// 从当前线程之后找到下一个可以恢复的线程，切换过去。
+    fn switch_next(&self) -> Option<Preempted> {
+        let current = self.current.get();
+        let next = (current + 1..N)
+            .chain(0..=current)
+            .find(|&i| matches!(self.peek(&self.slots[i]), Peek::Ready))?;
+        self.current.set(next);
+        match self.slots[next].replace(Thread::Running) {
+            Thread::Ready(preempted) => Some(preempted),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// 不取出内容的线程状态。
+enum Peek {
+    Empty,
+    Ready,
+    Other,
+}
+
+impl<T: Preemption, const N: usize> Preemption for &Threads<T, N> {
+    #[inline]
+    fn arm(&mut self, budget: u64) {
+        unsafe { &mut *self.timer.get() }.arm(budget)
+    }
+
+    #[inline]
+    fn disarm(&mut self) {
+        unsafe { &mut *self.timer.get() }.disarm()
+    }
+
+    /// 当前线程转为就绪，切换到下一个就绪的线程。
+    fn preempted(&mut self, preempted: Preempted) -> Preempted {
+        let current = self.current.get();
+        self.slots[current].set(Thread::Ready(preempted));
+        self.switch_next().unwrap()
+    }
+
+    /// 当前线程结束，切换到下一个就绪的线程。
+    fn exited(&mut self, ret: usize) -> Preempted {
+        let current = self.current.get();
+        self.slots[current].set(Thread::Finished(ret));
+        self.switch_next().expect("all threads exited")
+    }
+}
@@ -0,0 +1,407 @@
+use crate::{EntireContext, EntireResult, FastContext, FastResult};
+use core::ptr::NonNull;
+
+/// 定时器硬件。
+pub trait TimerDevice {
+    /// 读取当前时间。
+    fn now(&self) -> u64;
+
+    /// 在 `deadline` 时刻触发时钟中断，同时清除已经挂起的时钟中断。
+    fn set_deadline(&mut self, deadline: u64);
+}
+
+/// 内存映射的 `mtime`/`mtimecmp` 定时器，即 CLINT 或 ACLINT MTIMER。
+pub struct MmioTimer {
+    mtime: *const u64,
+    mtimecmp: *mut u64,
+}
+
+impl MmioTimer {
+    /// 基地址为 `base` 的 CLINT 上第 `hartid` 个硬件线程的定时器。
+    ///
+    /// # Safety
+    ///
+    /// `base` 必须是 CLINT 的基地址。
+    #[inline]
+    pub const unsafe fn clint(base: usize, hartid: usize) -> Self {
+        Self::aclint(base + 0x4000, hartid)
+    }
+
+    /// 基地址为 `base` 的 ACLINT MTIMER 上第 `hartid` 个硬件线程的定时器。
+    ///
+    /// # Safety
+    ///
+    /// `base` 必须是 ACLINT MTIMER 的基地址，且 `mtime` 位于默认的 0x7ff8 偏移处。
+    #[inline]
+    pub const unsafe fn aclint(base: usize, hartid: usize) -> Self {
+        Self {
+            mtime: (base + 0x7ff8) as _,
+            mtimecmp: (base + hartid * 8) as _,
+        }
+    }
+}
+
+impl TimerDevice for MmioTimer {
+    #[cfg(target_pointer_width = "64")]
+    #[inline]
+    fn now(&self) -> u64 {
+        unsafe { self.mtime.read_volatile() }
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[inline]
+    fn now(&self) -> u64 {
+        let lo = self.mtime.cast::<u32>();
+        let hi = unsafe { lo.add(1) };
+        loop {
+            let h = unsafe { hi.read_volatile() };
+            let l = unsafe { lo.read_volatile() };
+            if h == unsafe { hi.read_volatile() } {
+                break (h as u64) << 32 | l as u64;
+            }
+        }
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[inline]
+    fn set_deadline(&mut self, deadline: u64) {
+        unsafe { self.mtimecmp.write_volatile(deadline) }
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[inline]
+    fn set_deadline(&mut self, deadline: u64) {
+        // 先把低位写成最大值，避免写入过程中的中间值提前触发中断
+        let lo = self.mtimecmp.cast::<u32>();
+        unsafe {
+            lo.write_volatile(u32::MAX);
+            lo.add(1).write_volatile((deadline >> 32) as _);
+            lo.write_volatile(deadline as _);
+        }
+    }
+}
+
+/// Sstc 扩展提供的 `stimecmp` 定时器。
+#[cfg(feature = "riscv-s")]
+pub struct Sstc;
+
+#[cfg(feature = "riscv-s")]
+impl TimerDevice for Sstc {
+    #[cfg(target_pointer_width = "64")]
+    #[inline]
+    fn now(&self) -> u64 {
+        let time: u64;
+        unsafe { core::arch::asm!("rdtime {}", out(reg) time) };
+        time
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[inline]
+    fn now(&self) -> u64 {
+        loop {
+            let (hi, lo, check): (u32, u32, u32);
+            unsafe {
+                core::arch::asm!(
+                    "rdtimeh {}", "rdtime {}", "rdtimeh {}",
+                    out(reg) hi, out(reg) lo, out(reg) check,
+                )
+            };
+            if hi == check {
+                break (hi as u64) << 32 | lo as u64;
+            }
+        }
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[inline]
+    fn set_deadline(&mut self, deadline: u64) {
+        unsafe { core::arch::asm!("csrw 0x14d, {}", in(reg) deadline) };
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[inline]
+    fn set_deadline(&mut self, deadline: u64) {
+        unsafe {
+            core::arch::asm!(
+                "csrw 0x14d, {max}",
+                "csrw 0x15d, {hi}",
+                "csrw 0x14d, {lo}",
+                max = in(reg) u32::MAX,
+                hi  = in(reg) (deadline >> 32) as u32,
+                lo  = in(reg) deadline as u32,
+            )
+        };
+    }
+}
+
+/// 每层时间轮的槽数的对数。
+const SLOT_BITS: u32 = 6;
+/// 每层时间轮的槽数。
+const SLOTS: usize = 1 << SLOT_BITS;
+
+/// 软件定时器。
+///
+/// 定时器加入时间轮后不能移动，直到到期或被取消。
+pub struct TimerEntry {
+    deadline: u64,
+    callback: fn(usize),
+    arg: usize,
+    fast: bool,
+    next: Option<NonNull<TimerEntry>>,
+    location: Location,
+}
+
+/// 定时器所在的链表。
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Location {
+    /// 不在任何链表中。
+    Idle,
+    /// 在时间轮的某一层的某个槽中。
+    Wheel(usize, usize),
+    /// 已到期，等待在完整路径中执行。
+    Deferred,
+}
+
+impl TimerEntry {
+    /// 构造到期时在快速路径中调用 `callback(arg)` 的定时器。
+    ///
+    /// 回调在陷入栈上关中断地执行，只应该做轻量的操作。
+    #[inline]
+    pub const fn fast(callback: fn(usize), arg: usize) -> Self {
+        Self::new(callback, arg, true)
+    }
+
+    /// 构造到期时在完整路径中调用 `callback(arg)` 的定时器。
+    #[inline]
+    pub const fn deferred(callback: fn(usize), arg: usize) -> Self {
+        Self::new(callback, arg, false)
+    }
+
+    #[inline]
+    const fn new(callback: fn(usize), arg: usize, fast: bool) -> Self {
+        Self {
+            deadline: 0,
+            callback,
+            arg,
+            fast,
+            next: None,
+            location: Location::Idle,
+        }
+    }
+
+    /// 定时器已加入时间轮，尚未执行回调。
+    #[inline]
+    pub fn is_armed(&self) -> bool {
+        self.location != Location::Idle
+    }
+
+    /// 定时器的到期时刻。
+    #[inline]
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+}
+
+/// 侵入式定时器链表。
+type List = Option<NonNull<TimerEntry>>;
+
+/// 从链表中摘下 `entry`，返回是否找到。
+fn unlink(list: &mut List, entry: NonNull<TimerEntry>) -> bool {
+    let mut cursor = list;
+    while let Some(mut node) = *cursor {
+        if node == entry {
+            *cursor = unsafe { node.as_mut() }.next.take();
+            return true;
+        }
+        cursor = unsafe { &mut node.as_mut().next };
+    }
+    false
+}
+
+/// 分层时间轮。
+///
+/// 时间以 `1 << shift` 个计时周期为一拍，每层 64 个槽，第 `k` 层的一个槽跨越 `64^k` 拍。
+/// 超出 `LEVELS` 层范围的定时器放在最高层，到期前会被重新放置。
+///
+/// 时间轮总是把硬件定时器设置为下一个需要处理的时刻。
+/// 在时钟中断之外修改时间轮时，必须屏蔽时钟中断。
+pub struct TimerWheel<D, const LEVELS: usize> {
+    device: D,
+    shift: u32,
+    /// 下一个要处理的拍。
+    current: u64,
+    slots: [[List; SLOTS]; LEVELS],
+    /// 每层非空槽的位图。
+    occupied: [u64; LEVELS],
+    /// 已到期，等待在完整路径中执行的定时器。
+    deferred: List,
+}
+
+impl<D: TimerDevice, const LEVELS: usize> TimerWheel<D, LEVELS> {
+    /// 构造时间轮，以 `1 << shift` 个计时周期为一拍。
+    #[inline]
+    pub fn new(device: D, shift: u32) -> Self {
+        assert!(LEVELS > 0 && LEVELS as u32 * SLOT_BITS < u64::BITS);
+        let current = device.now() >> shift;
+        Self {
+            device,
+            shift,
+            current,
+            slots: [[None; SLOTS]; LEVELS],
+            occupied: [0; LEVELS],
+            deferred: None,
+        }
+    }
+
+    /// 定时器硬件。
+    #[inline]
+    pub fn device(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    /// 设置 `entry` 在 `deadline` 时刻到期，已经加入的定时器会被重新放置。
+    ///
+    /// # Safety
+    ///
+    /// `entry` 在到期或被取消之前不能移动或释放。
+    pub unsafe fn add(&mut self, mut entry: NonNull<TimerEntry>, deadline: u64) {
+        self.cancel(entry);
+        entry.as_mut().deadline = deadline;
+        self.insert(entry);
+        self.program();
+    }
+
+    /// 取消 `entry`，返回它是否尚未执行回调。
+    pub fn cancel(&mut self, mut entry: NonNull<TimerEntry>) -> bool {
+        let found = match unsafe { entry.as_ref() }.location {
+            Location::Idle => false,
+            Location::Wheel(level, slot) => {
+                let found = unlink(&mut self.slots[level][slot], entry);
+                if self.slots[level][slot].is_none() {
+                    self.occupied[level] &= !(1 << slot);
+                }
+                found
+            }
+            Location::Deferred => unlink(&mut self.deferred, entry),
+        };
+        unsafe { entry.as_mut() }.location = Location::Idle;
+        found
+    }
+
+    /// 在快速路径中处理时钟中断。
+    ///
+    /// 执行到期的快速回调并重新设置硬件定时器。
+    /// 有到期的非快速回调时转到完整路径执行它们，否则直接恢复。
+    ///
+    /// > **NOTICE** 必须先手工调用 `save_args`，或通过其他方式设置参数寄存器。
+    pub fn handle(&mut self, ctx: FastContext) -> FastResult
+    where
+        Self: 'static,
+    {
+        let now = self.device.now() >> self.shift;
+        while let Some(tick) = self.next_event().filter(|&tick| tick <= now) {
+            self.current = tick;
+            self.process();
+            self.current += 1;
+        }
+        self.current = self.current.max(now + 1);
+        self.program();
+        if self.deferred.is_some() {
+            ctx.continue_with(run_deferred::<D, LEVELS>, NonNull::from(self))
+        } else {
+            ctx.restore()
+        }
+    }
+
+    /// 按到期的拍把定时器放入时间轮。
+    fn insert(&mut self, mut entry: NonNull<TimerEntry>) {
+        let top = (LEVELS as u32 * SLOT_BITS) as u64;
+        let tick = (unsafe { entry.as_ref() }.deadline >> self.shift)
+            .clamp(self.current, self.current + (1 << top) - 1);
+        let delta = tick - self.current;
+        let level = (0..LEVELS)
+            .find(|&k| delta >> ((k as u32 + 1) * SLOT_BITS) == 0)
+            .unwrap();
+        let slot = (tick >> (level as u32 * SLOT_BITS)) as usize % SLOTS;
+        let e = unsafe { entry.as_mut() };
+        e.location = Location::Wheel(level, slot);
+        e.next = self.slots[level][slot].replace(entry);
+        self.occupied[level] |= 1 << slot;
+    }
+
+    /// 处理 `current` 这一拍：从高层向低层重新放置跨越的槽，然后取出第 0 层到期的定时器。
+    fn process(&mut self) {
+        for level in (1..LEVELS).rev() {
+            let bits = level as u32 * SLOT_BITS;
+            if self.current & ((1 << bits) - 1) == 0 {
+                let slot = (self.current >> bits) as usize % SLOTS;
+                let mut list = self.take(level, slot);
+                while let Some(mut entry) = list {
+                    list = unsafe { entry.as_mut() }.next.take();
+                    self.insert(entry);
+                }
+            }
+        }
+        let mut list = self.take(0, self.current as usize % SLOTS);
+        while let Some(mut entry) = list {
+            let e = unsafe { entry.as_mut() };
+            list = e.next.take();
+            // 超出范围的定时器被压到最高层，层数太少时会提前落到这里
+            if e.deadline >> self.shift > self.current {
+                self.insert(entry);
+            } else if e.fast {
+                e.location = Location::Idle;
+                (e.callback)(e.arg);
+            } else {
+                e.location = Location::Deferred;
+                e.next = self.deferred.replace(entry);
+            }
+        }
+    }
+
+    /// 取出一个槽中的所有定时器。
+    #[inline]
+    fn take(&mut self, level: usize, slot: usize) -> List {
+        self.occupied[level] &= !(1 << slot);
+        self.slots[level][slot].take()
+    }
+
+    /// 从 `current` 开始，下一个有定时器到期或需要重新放置的拍。
+    fn next_event(&self) -> Option<u64> {
+        (0..LEVELS)
+            .filter(|&level| self.occupied[level] != 0)
+            .map(|level| {
+                let bits = level as u32 * SLOT_BITS;
+                // 从 `current` 所在或之后的第一个完整的槽开始
+                let base = (self.current + (1 << bits) - 1) >> bits;
+                let offset = self.occupied[level].rotate_right((base % SLOTS as u64) as _);
+                (base + offset.trailing_zeros() as u64) << bits
+            })
+            .min()
+    }
+
+    /// 把硬件定时器设置为下一个需要处理的时刻。
+    #[inline]
+    fn program(&mut self) {
+        let deadline = match self.next_event() {
+            Some(tick) => tick << self.shift,
+            None => u64::MAX,
+        };
+        self.device.set_deadline(deadline);
+    }
+}
+
+/// 在完整路径中执行到期的非快速回调。
+extern "C" fn run_deferred<D: TimerDevice, const LEVELS: usize>(
+    ctx: EntireContext<NonNull<TimerWheel<D, LEVELS>>>,
+) -> EntireResult {
+    let (ctx, mail) = ctx.split();
+    let wheel = unsafe { mail.get().as_mut() };
+    while let Some(mut entry) = wheel.deferred {
+        let e = unsafe { entry.as_mut() };
+        wheel.deferred = e.next.take();
+        e.location = Location::Idle;
+        (e.callback)(e.arg);
+    }
+    ctx.restore()
+}
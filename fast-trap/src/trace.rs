@@ -0,0 +1,173 @@
+use crate::{read_cause, read_cycle, read_epc, read_tval, FastContext, FastResult, FreeTrapStack};
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// 陷入事件缓冲区的容量。
+pub const TRACE_CAPACITY: usize = 64;
+
+/// 陷入事件。
+#[derive(Clone, Copy, Debug)]
+pub struct TrapEvent {
+    /// 陷入原因。
+    pub cause: usize,
+    /// 陷入时的 pc。
+    pub epc: usize,
+    /// 陷入附加信息。
+    pub tval: usize,
+    /// 快速路径的处理结果。
+    pub result: FastResult,
+    /// 进入快速路径时的周期数。
+    pub enter: usize,
+    /// 离开快速路径时的周期数。
+    pub exit: usize,
+}
+
+impl TrapEvent {
+    /// 快速路径用去的周期数。
+    #[inline]
+    pub fn cycles(&self) -> usize {
+        self.exit.wrapping_sub(self.enter)
+    }
+}
+
+/// 陷入事件缓冲区。
+///
+/// 单生产者单消费者的无锁环形缓冲区：陷入处理在快速路径返回时写入事件，
+/// 控制流通过 [`TraceBuffer::drain`] 取出。缓冲区满时丢弃新的事件并计数。
+/// 嵌套陷入的事件先于外层写入。
+///
+/// 每个硬件线程的陷入栈应该使用自己的缓冲区。
+pub struct TraceBuffer {
+    events: UnsafeCell<[MaybeUninit<TrapEvent>; TRACE_CAPACITY]>,
+    /// 已写入的事件数。
+    head: AtomicUsize,
+    /// 已取出的事件数。
+    tail: AtomicUsize,
+    /// 因缓冲区满丢弃的事件数。
+    dropped: AtomicUsize,
+}
+
+unsafe impl Sync for TraceBuffer {}
+
+impl TraceBuffer {
+    /// 创建空的缓冲区。
+    pub const fn new() -> Self {
+        const EMPTY: MaybeUninit<TrapEvent> = MaybeUninit::uninit();
+        Self {
+            events: UnsafeCell::new([EMPTY; TRACE_CAPACITY]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// 取出所有已写入的事件。
+    ///
+    /// 同一时刻只能有一个消费者。
+    #[inline]
+    pub fn drain(&self) -> Drain {
+        Drain(self)
+    }
+
+    /// 因缓冲区满丢弃的事件数。
+    #[inline]
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn push(&self, event: TrapEvent) {
+        let head = self.head.load(Ordering::Relaxed);
+        if head.wrapping_sub(self.tail.load(Ordering::Acquire)) == TRACE_CAPACITY {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        unsafe { (*self.slot(head)).write(event) };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+    }
+
+    fn pop(&self) -> Option<TrapEvent> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+        let event = unsafe { (*self.slot(tail)).assume_init() };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(event)
+    }
+
+    #[inline]
+    fn slot(&self, index: usize) -> *mut MaybeUninit<TrapEvent> {
+        let events = self.events.get() as *mut MaybeUninit<TrapEvent>;
+        unsafe { events.add(index % TRACE_CAPACITY) }
+    }
+}
+
+impl Default for TraceBuffer {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 取出陷入事件的迭代器。
+pub struct Drain<'a>(&'a TraceBuffer);
+
+impl Iterator for Drain<'_> {
+    type Item = TrapEvent;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.pop()
+    }
+}
+
+impl FreeTrapStack {
+    /// 把在这个陷入栈上发生的陷入事件记录到 `buffer`。
+    ///
+    /// 嵌套陷入也记录到同一个缓冲区。
+    pub fn set_trace(&mut self, buffer: &'static TraceBuffer) {
+        unsafe { self.0.as_mut() }.trace = Some(buffer);
+    }
+}
+
+/// 记录陷入事件和统计的快速路径入口。
+///
+/// 打开 `trace` 特性时，陷入处理例程通过它调用快速路径函数。
+#[allow(clippy::too_many_arguments)]
+#[export_name = "fast_trap_traced_handler"]
+extern "C" fn traced_fast_handler(
+    ctx: FastContext,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> FastResult {
+    let enter = read_cycle();
+    let handler = ctx.handler();
+    let fast_handler = handler.fast_handler;
+    let root = unsafe { &*handler.root() };
+    let (trace, stats) = (root.trace, root.stats);
+    let (cause, epc, tval) = (read_cause(), read_epc(), read_tval());
+    let result = fast_handler(ctx, a1, a2, a3, a4, a5, a6, a7);
+    let exit = read_cycle();
+    if let Some(stats) = stats {
+        stats.record(cause, result, exit.wrapping_sub(enter));
+    }
+    if let Some(buffer) = trace {
+        buffer.push(TrapEvent {
+            cause,
+            epc,
+            tval,
+            result,
+            enter,
+            exit,
+        });
+    }
+    result
+}
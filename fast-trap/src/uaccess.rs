@@ -0,0 +1,175 @@
+use crate::{
+    read_cause, read_epc, with_user_access, write_epc, EntireContextSeparated, FastContext,
+    FastResult,
+};
+use core::{arch::asm, mem::size_of, slice};
+
+#[cfg(target_arch = "riscv32")]
+macro_rules! entry {
+    () => {
+        ".balign 4\n.word"
+    };
+}
+#[cfg(target_arch = "riscv64")]
+macro_rules! entry {
+    () => {
+        ".balign 8\n.dword"
+    };
+}
+
+/// 访问用户内存失败。
+#[derive(Debug)]
+pub struct UserFault;
+
+/// 异常修复表的表项。
+///
+/// 位于 `insn` 的访存指令发生异常时，跳转到 `fixup` 继续执行。
+/// 表项由访存函数以内联汇编放进 `ex_table` 段，
+/// 链接器为这个段生成 `__start_ex_table` 和 `__stop_ex_table` 两个符号。
+///
+/// > **NOTICE** 链接脚本使用 `--gc-sections` 时，应该以 `KEEP(*(ex_table))` 保留这个段。
+#[repr(C)]
+struct ExceptionEntry {
+    insn: usize,
+    fixup: usize,
+}
+
+/// 在异常修复表中查找 `pc` 的修复地址。
+pub fn search_exception_table(pc: usize) -> Option<usize> {
+    extern "C" {
+        #[link_name = "__start_ex_table"]
+        static START: ExceptionEntry;
+        #[link_name = "__stop_ex_table"]
+        static STOP: ExceptionEntry;
+    }
+    let start = unsafe { &START } as *const ExceptionEntry;
+    let stop = unsafe { &STOP } as *const ExceptionEntry;
+    let len = (stop as usize - start as usize) / size_of::<ExceptionEntry>();
+    unsafe { slice::from_raw_parts(start, len) }
+        .iter()
+        .find(|entry| entry.insn == pc)
+        .map(|entry| entry.fixup)
+}
+
+/// 陷入原因是可以修复的访存异常。
+///
+/// 包括读写的访问异常和页异常。
+#[inline]
+const fn is_memory_fault(cause: usize) -> bool {
+    const LOAD_ACCESS_FAULT: usize = 5;
+    const STORE_ACCESS_FAULT: usize = 7;
+    const LOAD_PAGE_FAULT: usize = 13;
+    const STORE_PAGE_FAULT: usize = 15;
+    matches!(
+        cause,
+        LOAD_ACCESS_FAULT | STORE_ACCESS_FAULT | LOAD_PAGE_FAULT | STORE_PAGE_FAULT
+    )
+}
+
+/// 如果当前陷入是修复表中的访存指令引起的，将 `epc` 改写为修复地址。
+#[inline]
+fn fixup() -> bool {
+    if !is_memory_fault(read_cause()) {
+        return false;
+    }
+    match search_exception_table(read_epc()) {
+        Some(fixup) => {
+            write_epc(fixup);
+            true
+        }
+        None => false,
+    }
+}
+
+impl FastContext {
+    /// 在快速路径修复当前的访存异常。
+    ///
+    /// 返回 `true` 表示异常由用户内存访问函数引起，`epc` 已改写为修复地址。
+    #[inline]
+    pub fn fixup_exception(&mut self) -> bool {
+        fixup()
+    }
+}
+
+impl EntireContextSeparated {
+    /// 在完整路径修复当前的访存异常。
+    ///
+    /// 返回 `true` 表示异常由用户内存访问函数引起，`epc` 已改写为修复地址。
+    #[inline]
+    pub fn fixup_exception(&mut self) -> bool {
+        fixup()
+    }
+}
+
+/// 快速路径的异常修复。
+///
+/// 如果异常由用户内存访问函数引起，改写 `epc` 并恢复，访问函数会返回 [`UserFault`]；
+/// 否则交还上下文，由调用者继续处理。
+///
+/// > **NOTICE** 必须先手工调用 `save_args`，或通过其他方式设置参数寄存器。
+#[inline]
+pub fn fast_fixup_exception(mut ctx: FastContext) -> Result<FastResult, FastContext> {
+    if ctx.fixup_exception() {
+        Ok(ctx.restore())
+    } else {
+        Err(ctx)
+    }
+}
+
+/// 从用户地址 `src` 复制 `dst.len()` 个字节到 `dst`。
+///
+/// 访问期间允许当前特权级访问用户页。
+/// 访存异常需要由陷入处理函数通过 [`fast_fixup_exception`] 或 `fixup_exception` 修复。
+///
+/// # Safety
+///
+/// `src` 开始的 `dst.len()` 个字节必须都在用户地址空间中。
+#[inline]
+pub unsafe fn copy_from_user(dst: &mut [u8], src: *const u8) -> Result<(), UserFault> {
+    raw_copy(dst.as_mut_ptr(), src, dst.len())
+}
+
+/// 从 `src` 复制 `src.len()` 个字节到用户地址 `dst`。
+///
+/// 访问期间允许当前特权级访问用户页。
+/// 访存异常需要由陷入处理函数通过 [`fast_fixup_exception`] 或 `fixup_exception` 修复。
+///
+/// # Safety
+///
+/// `dst` 开始的 `src.len()` 个字节必须都在用户地址空间中。
+#[inline]
+pub unsafe fn copy_to_user(dst: *mut u8, src: &[u8]) -> Result<(), UserFault> {
+    raw_copy(dst, src.as_ptr(), src.len())
+}
+
+/// 逐字节复制，读写指令都登记在修复表中。
+///
+/// 发生异常时跳出循环，`len` 保留未复制的字节数。
+#[inline(never)]
+unsafe fn raw_copy(dst: *mut u8, src: *const u8, mut len: usize) -> Result<(), UserFault> {
+    with_user_access(|| {
+        asm!(
+            "   beqz {len}, 3f
+             1: lbu  {byte}, 0({src})
+             2: sb   {byte}, 0({dst})
+                addi {src},  {src},  1
+                addi {dst},  {dst},  1
+                addi {len},  {len}, -1
+                bnez {len},  1b
+             3:
+                .pushsection ex_table, \"a\"
+            ",
+            concat!(entry!(), " 1b, 3b"),
+            concat!(entry!(), " 2b, 3b"),
+            ".popsection",
+            src  = inout(reg) src => _,
+            dst  = inout(reg) dst => _,
+            len  = inout(reg) len,
+            byte = out(reg) _,
+        )
+    });
+    match len {
+        0 => Ok(()),
+        _ => Err(UserFault),
+    }
+}
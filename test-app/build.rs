@@ -27,6 +27,9 @@ SECTIONS {{
         *(.rodata .rodata.*)
         *(.srodata .srodata.*)
     }}
+    ex_table : ALIGN(8) {{
+        KEEP(*(ex_table))
+    }}
     .data : {{
         *(.data .data.*)
         *(.sdata .sdata.*)
@@ -0,0 +1,104 @@
+//! AIA 外部中断测试。
+//!
+//! 只在 `aia=aplic-imsic` 的 qemu `virt` 上运行：UART 中断经 APLIC 转换为消息，
+//! 核间中断直接写入自己的中断文件，快速路径都通过 `mtopei` 认领。
+
+use crate::{
+    plic_uart::{ETBEI, IER, UART_IRQ},
+    StackRef, APLIC, BOOT_HART, FREE_STACK, HARTS, IMSIC, UART_BASE,
+};
+use core::ptr::NonNull;
+use fast_trap::{
+    Aplic, FastContext, FastResult, FlowContext, FreeTrapStack, Imsic, ImsicFile, IrqDispatcher,
+    IrqHandler, SourceMode,
+};
+use rcore_console::log;
+use riscv::register::*;
+
+/// 用作核间中断的中断编号。
+const IPI: usize = 1;
+
+static mut IRQS: Option<IrqDispatcher<Imsic, 64>> = None;
+static mut HANDLED: usize = 0;
+
+pub(crate) fn run() {
+    let mut context = FlowContext::ZERO;
+    let loaded = FreeTrapStack::new(
+        StackRef(unsafe { &mut FREE_STACK }),
+        NonNull::from(&mut context),
+        fast_handler,
+    )
+    .unwrap()
+    .load();
+
+    let aplic = unsafe { Aplic::new(APLIC) };
+    aplic.init_msi(unsafe { IMSIC }, unsafe { HARTS });
+    aplic.configure(UART_IRQ, SourceMode::LevelHigh);
+    aplic.route(UART_IRQ, unsafe { BOOT_HART }, UART_IRQ);
+    aplic.enable(UART_IRQ);
+    unsafe {
+        Imsic::init(0);
+        Imsic::enable(UART_IRQ);
+        Imsic::enable(IPI);
+    }
+    let irqs = unsafe { IRQS.insert(IrqDispatcher::new(Imsic)) };
+    irqs.register(UART_IRQ, IrqHandler::Fast(uart));
+    irqs.register(IPI, IrqHandler::Entire(ipi));
+
+    unsafe { ((UART_BASE + IER) as *mut u8).write_volatile(ETBEI) };
+    wait(1);
+    unsafe { ImsicFile::hart(IMSIC, BOOT_HART) }.send(IPI);
+    wait(2);
+
+    aplic.disable(UART_IRQ);
+    unsafe {
+        Imsic::disable(UART_IRQ);
+        Imsic::disable(IPI);
+        IRQS = None;
+    }
+    log::info!("aia interrupts dispatched");
+    drop(loaded);
+}
+
+/// 开中断等待第 `n` 次处理完成。
+fn wait(n: usize) {
+    unsafe {
+        mie::set_mext();
+        mstatus::set_mie();
+        while (&HANDLED as *const usize).read_volatile() != n {
+            riscv::asm::wfi();
+        }
+        mstatus::clear_mie();
+        mie::clear_mext();
+    }
+}
+
+fn uart(_irq: usize) {
+    unsafe {
+        ((UART_BASE + IER) as *mut u8).write_volatile(0);
+        HANDLED += 1;
+    }
+}
+
+fn ipi(irq: usize) {
+    log::info!("msi ipi {irq} in entire path");
+    unsafe { HANDLED += 1 };
+}
+
+extern "C" fn fast_handler(
+    mut ctx: FastContext,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> FastResult {
+    ctx.save_args(a1, a2, a3, a4, a5, a6, a7);
+    assert!(matches!(
+        mcause::read().cause(),
+        mcause::Trap::Interrupt(mcause::Interrupt::MachineExternal)
+    ));
+    unsafe { IRQS.as_mut() }.unwrap().dispatch(ctx)
+}
@@ -0,0 +1,132 @@
+//! 调用返回测试。
+//!
+//! 快速路径用 `call_with` 在另一个上下文中调用一个函数，函数返回时如同发生陷入，
+//! 完成处理函数检查返回值 a0/a1，然后回到根控制流。
+
+use crate::{cause, StackRef, FREE_STACK, THREAD_STACKS};
+use core::{arch::asm, ptr::NonNull};
+use fast_trap::{soft_trap, FastContext, FastResult, FlowContext, FreeTrapStack};
+use rcore_console::log;
+use riscv::register::*;
+
+static mut ROOT: FlowContext = FlowContext::ZERO;
+static mut CALLEE: FlowContext = FlowContext::ZERO;
+/// 根控制流被打断的位置。
+static mut ROOT_PC: usize = 0;
+/// 完成处理函数收到的 a0/a1。
+static mut RETURNED: Option<(usize, usize)> = None;
+
+pub(crate) fn run() {
+    let [stack, _, _] = unsafe { &mut THREAD_STACKS };
+    unsafe {
+        CALLEE.sp = stack.as_mut_ptr_range().end as usize & !15;
+        CALLEE.pc = callee as usize;
+        CALLEE.a[0] = 7;
+        CALLEE.a[1] = 5;
+        asm!("mv {}, gp", "mv {}, tp", out(reg) CALLEE.gp, out(reg) CALLEE.tp);
+    }
+    let loaded = FreeTrapStack::new(
+        StackRef(unsafe { &mut FREE_STACK }),
+        NonNull::from(unsafe { &mut ROOT }),
+        fast_handler,
+    )
+    .unwrap()
+    .load();
+    unsafe { soft_trap(cause::CALL_WITH) };
+    assert_eq!(Some((12, 2)), unsafe { RETURNED });
+    log::info!("call_with completed with (12, 2)");
+    drop(loaded);
+}
+
+/// 被调用的函数，返回 (a0 + a1, a0 - a1)。
+#[naked]
+unsafe extern "C" fn callee() -> ! {
+    asm!(
+        "   add  t0, a0, a1
+            sub  a1, a0, a1
+            mv   a0, t0
+            ret
+        ",
+        options(noreturn),
+    )
+}
+
+extern "C" fn fast_handler(
+    mut ctx: FastContext,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> FastResult {
+    assert_eq!(cause::CALL_WITH, read_cause());
+    ctx.save_args(a1, a2, a3, a4, a5, a6, a7);
+    unsafe { ROOT_PC = read_epc() };
+    // 调用返回例程在被调用的函数的特权级上执行
+    set_previous_privilege_to_kernel();
+    ctx.swap_context(NonNull::from(unsafe { &mut CALLEE }));
+    ctx.call_with(completion)
+        .unwrap_or_else(|_| unreachable!("call_with at level 1"))
+}
+
+/// 完成处理函数：记录返回值，回到根控制流。
+///
+/// 被调用的函数和快速路径都不改变 s 寄存器，它们仍是根控制流的值，所以直接恢复即可。
+extern "C" fn completion(
+    mut ctx: FastContext,
+    a1: usize,
+    _a2: usize,
+    _a3: usize,
+    _a4: usize,
+    _a5: usize,
+    _a6: usize,
+    _a7: usize,
+) -> FastResult {
+    unsafe { RETURNED = Some((ctx.a0(), a1)) };
+    ctx.swap_context(NonNull::from(unsafe { &mut ROOT }));
+    write_epc(unsafe { ROOT_PC });
+    set_previous_privilege_to_kernel();
+    ctx.restore()
+}
+
+#[cfg(feature = "m-mode")]
+fn read_cause() -> usize {
+    mcause::read().bits()
+}
+
+#[cfg(feature = "m-mode")]
+fn read_epc() -> usize {
+    mepc::read()
+}
+
+#[cfg(feature = "m-mode")]
+fn write_epc(pc: usize) {
+    mepc::write(pc)
+}
+
+#[cfg(feature = "m-mode")]
+fn set_previous_privilege_to_kernel() {
+    unsafe { mstatus::set_mpp(mstatus::MPP::Machine) };
+}
+
+#[cfg(feature = "s-mode")]
+fn read_cause() -> usize {
+    scause::read().bits()
+}
+
+#[cfg(feature = "s-mode")]
+fn read_epc() -> usize {
+    sepc::read()
+}
+
+#[cfg(feature = "s-mode")]
+fn write_epc(pc: usize) {
+    sepc::write(pc)
+}
+
+#[cfg(feature = "s-mode")]
+fn set_previous_privilege_to_kernel() {
+    unsafe { sstatus::set_spp(sstatus::SPP::Supervisor) };
+}
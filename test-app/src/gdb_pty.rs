@@ -0,0 +1,85 @@
+//! 连接真实 gdb 的调试桩测试。
+//!
+//! 打开 `gdb-pty` 特性时不运行其他测试，UART 只承载 GDB 远程协议。
+//! 目标函数执行 `ebreak` 进入调试桩，由 `cargo gdb-stub` 通过 pty 连接的 gdb
+//! 跳过断点指令、单步、在 `ret` 处设置断点并继续，然后断开连接。目标函数返回 7 则测试通过。
+
+use crate::{StackRef, FREE_STACK, TEST, UART};
+use core::{arch::asm, ptr::NonNull};
+use fast_trap::{
+    fast_fixup_exception, FastContext, FastResult, FlowContext, FreeTrapStack, GdbConnection,
+    GdbStub,
+};
+
+/// 以 UART 为调试器连接。
+struct Uart;
+
+impl GdbConnection for Uart {
+    #[inline]
+    fn read(&mut self) -> u8 {
+        unsafe { UART.assume_init_mut() }.receive()
+    }
+
+    #[inline]
+    fn write(&mut self, byte: u8) {
+        unsafe { UART.assume_init_mut() }.send(byte)
+    }
+}
+
+static mut STUB: GdbStub<Uart, 4> = GdbStub::new(Uart);
+
+/// 目标函数，每条指令 4 字节：进入调试器，然后计算 5 + 1 + 1。
+///
+/// gdb 脚本按符号名找到它。
+#[naked]
+#[no_mangle]
+unsafe extern "C" fn gdb_pty_target() -> usize {
+    asm!(
+        "   .option push
+            .option norvc
+            ebreak
+            li   a0, 5
+            addi a0, a0, 1
+            addi a0, a0, 1
+            ret
+            .option pop
+        ",
+        options(noreturn),
+    )
+}
+
+pub(crate) fn run() -> ! {
+    let mut context = FlowContext::ZERO;
+    let loaded = FreeTrapStack::new(
+        StackRef(unsafe { &mut FREE_STACK }),
+        NonNull::from(&mut context),
+        fast_handler,
+    )
+    .unwrap()
+    .load();
+    let ans = unsafe { gdb_pty_target() };
+    drop(loaded);
+    let test = unsafe { &*TEST };
+    if ans == 7 {
+        test.pass()
+    } else {
+        test.fail(ans as _)
+    }
+}
+
+extern "C" fn fast_handler(
+    mut ctx: FastContext,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> FastResult {
+    ctx.save_args(a1, a2, a3, a4, a5, a6, a7);
+    // gdb 探测内存时可能访问无效地址
+    fast_fixup_exception(ctx)
+        .or_else(|ctx| ctx.enter_gdb(unsafe { &mut STUB }))
+        .unwrap_or_else(|_| unreachable!())
+}
@@ -0,0 +1,189 @@
+//! 调试桩测试。
+//!
+//! 用脚本代替调试器：在目标函数中设置断点、继续、读寄存器、单步、读写内存，然后断开连接，
+//! 检查调试桩的每个回复。访问无效地址的请求引起嵌套陷入，断开后目标仍要回到原来的特权级。
+
+use crate::{StackRef, FREE_STACK};
+use core::{arch::asm, fmt::Write, mem::size_of, ptr::NonNull};
+use fast_trap::{
+    fast_fixup_exception, FastContext, FastResult, FlowContext, FreeTrapStack, GdbConnection,
+    GdbStub,
+};
+use rcore_console::log;
+
+const PACKETS: usize = 14;
+/// qemu `virt` 上没有映射任何设备的地址。
+const UNMAPPED: usize = 0x80_0000;
+
+/// 按脚本发送请求、记录回复的连接。
+struct Script {
+    requests: [Packet; PACKETS],
+    replies: [Packet; PACKETS],
+    /// 正在发送的请求和其中的位置。
+    sending: (usize, usize),
+    /// 正在记录的回复，`None` 表示不在数据包内。
+    receiving: (usize, Option<usize>),
+}
+
+#[derive(Clone, Copy)]
+struct Packet {
+    buf: [u8; 48],
+    len: usize,
+}
+
+impl Packet {
+    const EMPTY: Self = Self {
+        buf: [0; 48],
+        len: 0,
+    };
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl Write for Packet {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.buf[self.len..][..s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+impl GdbConnection for Script {
+    fn read(&mut self) -> u8 {
+        let (i, pos) = self.sending;
+        let packet = self.requests[i].as_bytes();
+        let sum = packet.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        let hex = b"0123456789abcdef";
+        let byte = match pos {
+            0 => b'$',
+            p if p <= packet.len() => packet[p - 1],
+            p if p == packet.len() + 1 => b'#',
+            p if p == packet.len() + 2 => hex[(sum >> 4) as usize],
+            _ => hex[(sum & 0xf) as usize],
+        };
+        self.sending = if pos == packet.len() + 3 {
+            (i + 1, 0)
+        } else {
+            (i, pos + 1)
+        };
+        byte
+    }
+
+    fn write(&mut self, byte: u8) {
+        match (self.receiving, byte) {
+            ((i, None), b'$') => self.receiving = (i, Some(0)),
+            ((i, Some(_)), b'#') => self.receiving = (i + 1, None),
+            ((i, Some(_)), _) => {
+                let reply = &mut self.replies[i];
+                reply.buf[reply.len] = byte;
+                reply.len += 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+static mut STUB: GdbStub<Script, 4> = GdbStub::new(Script {
+    requests: [Packet::EMPTY; PACKETS],
+    replies: [Packet::EMPTY; PACKETS],
+    sending: (0, 0),
+    receiving: (0, None),
+});
+
+/// 目标函数，每条指令 4 字节：进入调试器，然后计算 5 + 1 + 1。
+#[naked]
+unsafe extern "C" fn target() -> usize {
+    asm!(
+        "   .option push
+            .option norvc
+            ebreak
+            li   a0, 5
+            addi a0, a0, 1
+            addi a0, a0, 1
+            ret
+            .option pop
+        ",
+        options(noreturn),
+    )
+}
+
+pub(crate) fn run() {
+    const WIDTH: usize = size_of::<usize>() * 2;
+    let base = target as usize;
+    let word = |val: usize| packet(format_args!("{:0WIDTH$x}", val.swap_bytes()));
+    unsafe { STUB.connection() }.requests = [
+        packet(format_args!("?")),
+        packet(format_args!("P20={:0WIDTH$x}", (base + 4).swap_bytes())),
+        packet(format_args!("Z0,{:x},4", base + 12)),
+        packet(format_args!("c")),
+        packet(format_args!("p0a")),
+        packet(format_args!("z0,{:x},4", base + 12)),
+        packet(format_args!("s")),
+        packet(format_args!("p0a")),
+        packet(format_args!("p20")),
+        packet(format_args!("m{:x},4", base + 4)),
+        packet(format_args!("m{UNMAPPED:x},4")),
+        packet(format_args!("M{UNMAPPED:x},1:00")),
+        packet(format_args!("Z0,{UNMAPPED:x},4")),
+        packet(format_args!("D")),
+    ];
+    let expected = [
+        packet(format_args!("S05")),
+        packet(format_args!("OK")),
+        packet(format_args!("OK")),
+        packet(format_args!("S05")),
+        word(6),
+        packet(format_args!("OK")),
+        packet(format_args!("S05")),
+        word(7),
+        word(base + 16),
+        // li a0, 5
+        packet(format_args!("13055000")),
+        packet(format_args!("E14")),
+        packet(format_args!("E14")),
+        packet(format_args!("E14")),
+        packet(format_args!("OK")),
+    ];
+
+    let mut context = FlowContext::ZERO;
+    let loaded = FreeTrapStack::new(
+        StackRef(unsafe { &mut FREE_STACK }),
+        NonNull::from(&mut context),
+        fast_handler,
+    )
+    .unwrap()
+    .load();
+    assert_eq!(7, unsafe { target() });
+    drop(loaded);
+
+    let replies = &unsafe { STUB.connection() }.replies;
+    for (reply, expected) in replies.iter().zip(&expected) {
+        assert_eq!(expected.as_bytes(), reply.as_bytes());
+    }
+    log::info!("gdb stub served {PACKETS} packets");
+}
+
+fn packet(args: core::fmt::Arguments) -> Packet {
+    let mut packet = Packet::EMPTY;
+    packet.write_fmt(args).unwrap();
+    packet
+}
+
+extern "C" fn fast_handler(
+    mut ctx: FastContext,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> FastResult {
+    ctx.save_args(a1, a2, a3, a4, a5, a6, a7);
+    // 调试桩读写无法访问的内存时发生嵌套陷入
+    fast_fixup_exception(ctx)
+        .or_else(|ctx| ctx.enter_gdb(unsafe { &mut STUB }))
+        .unwrap_or_else(|_| unreachable!())
+}
@@ -0,0 +1,150 @@
+//! IPC 往返测试。
+//!
+//! 客户端和服务端任务运行在 M 态，比较快速路径 IPC 往返和两次完整路径陷入的周期数。
+
+use crate::{cause, StackRef, FREE_STACK, THREAD_STACKS};
+use core::{arch::asm, ptr::NonNull};
+use fast_trap::{
+    ipc_syscall, soft_trap, Endpoint, EntireContext, EntireResult, FastContext, FastResult,
+    FlowContext, FreeTrapStack, IpcScheduler, IpcTask, IPC_CALL, IPC_REPLY_RECV,
+};
+use rcore_console::log;
+use riscv::register::*;
+
+/// 空调用，经过完整路径。
+const NULL_CALL: usize = 3;
+/// 结束测试，回到根控制流。
+const EXIT: usize = 4;
+/// 往返次数。
+const ROUNDS: usize = 1000;
+
+static mut ROOT: IpcTask = IpcTask::new(FlowContext::ZERO);
+static mut CLIENT: IpcTask = IpcTask::new(FlowContext::ZERO);
+static mut SERVER: IpcTask = IpcTask::new(FlowContext::ZERO);
+static mut EP: Endpoint = Endpoint::new();
+
+pub(crate) fn run() {
+    let [client, server, _] = unsafe { &mut THREAD_STACKS };
+    unsafe {
+        CLIENT.context = task_context(client_main, client);
+        SERVER.context = task_context(server_main, server);
+    }
+    let loaded = FreeTrapStack::new(
+        StackRef(unsafe { &mut FREE_STACK }),
+        NonNull::from(unsafe { &mut ROOT.context }),
+        fast_handler,
+    )
+    .unwrap()
+    .load();
+    unsafe { soft_trap(cause::IPC) };
+    drop(loaded);
+}
+
+/// 构造在 `stack` 上执行 `entry` 的任务上下文。
+fn task_context(entry: extern "C" fn() -> !, stack: &mut [u8]) -> FlowContext {
+    let mut context = FlowContext::ZERO;
+    context.sp = stack.as_mut_ptr_range().end as usize & !15;
+    context.pc = entry as usize;
+    unsafe { asm!("mv {}, gp", "mv {}, tp", out(reg) context.gp, out(reg) context.tp) };
+    context
+}
+
+extern "C" fn client_main() -> ! {
+    // 预热
+    ipc_syscall(IPC_CALL, 0, [0; 6]);
+
+    let t0 = mcycle::read64();
+    for i in 0..ROUNDS {
+        assert_eq!(i + 1, ipc_syscall(IPC_CALL, 0, [i, 0, 0, 0, 0, 0])[0]);
+    }
+    let t1 = mcycle::read64();
+    for _ in 0..ROUNDS {
+        ipc_syscall(NULL_CALL, 0, [0; 6]);
+        ipc_syscall(NULL_CALL, 0, [0; 6]);
+    }
+    let t2 = mcycle::read64();
+
+    log::info!(
+        "ipc round trip: {} cycles, entire path x2: {} cycles",
+        (t1 - t0) / ROUNDS as u64,
+        (t2 - t1) / ROUNDS as u64,
+    );
+    ipc_syscall(EXIT, 0, [0; 6]);
+    unreachable!()
+}
+
+extern "C" fn server_main() -> ! {
+    let mut msg = ipc_syscall(IPC_REPLY_RECV, 0, [0; 6]);
+    loop {
+        msg[0] += 1;
+        msg = ipc_syscall(IPC_REPLY_RECV, 0, msg);
+    }
+}
+
+/// 这个测试里 IPC 不会唤醒其他任务。
+struct NoReady;
+
+impl IpcScheduler for NoReady {
+    fn ready(&mut self, _: NonNull<IpcTask>) {
+        unreachable!()
+    }
+}
+
+extern "C" fn fast_handler(
+    mut ctx: FastContext,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> FastResult {
+    use {mcause::Exception as E, mcause::Trap as T};
+    let msg = [a1, a2, a3, a4, a5, a6];
+    let cause = mcause::read();
+    match cause.cause() {
+        T::Exception(E::MachineEnvCall) => match a7 {
+            // 客户端和服务端的上下文都嵌在 `IpcTask` 中
+            IPC_CALL => {
+                unsafe { fast_trap::call(ctx, &mut EP, msg) }.unwrap_or_else(|_| unreachable!())
+            }
+            IPC_REPLY_RECV => unsafe { fast_trap::reply_recv(ctx, &mut EP, msg, &mut NoReady) }
+                .unwrap_or_else(|ctx| ctx.switch_to(NonNull::from(unsafe { &mut CLIENT.context }))),
+            NULL_CALL => {
+                ctx.save_args(a1, a2, a3, a4, a5, a6, a7);
+                ctx.continue_with(null_call, ())
+            }
+            EXIT => ctx.switch_to(NonNull::from(unsafe { &mut ROOT.context })),
+            _ => unreachable!(),
+        },
+        T::Exception(E::Unknown) if cause.bits() == cause::IPC => {
+            ctx.save_args(a1, a2, a3, a4, a5, a6, a7);
+            ctx.continue_with(start, ())
+        }
+        T::Exception(_) | T::Interrupt(_) => unreachable!(),
+    }
+}
+
+/// 保存根控制流，启动服务端。
+extern "C" fn start(ctx: EntireContext) -> EntireResult {
+    let (mut ctx, _) = ctx.split();
+    let root = ctx.regs();
+    root.pc = mepc::read();
+    unsafe { asm!("mv {}, gp", "mv {}, tp", out(reg) root.gp, out(reg) root.tp) };
+    ctx.swap_context(NonNull::from(unsafe { &mut SERVER.context }));
+    let server = ctx.regs();
+    unsafe {
+        asm!("mv gp, {}", "mv tp, {}", in(reg) server.gp, in(reg) server.tp);
+        mepc::write(server.pc);
+        mstatus::set_mpp(mstatus::MPP::Machine);
+    }
+    ctx.restore()
+}
+
+/// 越过 `ecall` 返回。
+extern "C" fn null_call(ctx: EntireContext) -> EntireResult {
+    let (ctx, _) = ctx.split();
+    mepc::write(mepc::read() + 4);
+    ctx.restore()
+}
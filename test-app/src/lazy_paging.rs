@@ -0,0 +1,129 @@
+//! 按需分页测试。
+//!
+//! 打开 Sv39 分页，以 1 GiB 大页恒等映射设备和内存，`LAZY` 开始的几页只有页表没有映射。
+//! 第一次访问这些页引起页异常，完整路径中的页异常处理器分配物理页并建立映射。
+
+use crate::{StackRef, FREE_STACK};
+use core::ptr::NonNull;
+use fast_trap::{
+    AccessKind, EntireContextSeparated, EntireResult, FastContext, FastResult, FlowContext,
+    FreeTrapStack, PageFault, PageFaultHandler, PageFaultResult,
+};
+use rcore_console::log;
+use riscv::register::satp;
+
+/// 按需分配的虚地址区域。
+const LAZY: usize = 0x1_0000_0000;
+/// 按需分配的页数。
+const PAGES: usize = 4;
+
+const V: usize = 1 << 0;
+const R: usize = 1 << 1;
+const W: usize = 1 << 2;
+const X: usize = 1 << 3;
+const A: usize = 1 << 6;
+const D: usize = 1 << 7;
+
+#[repr(C, align(4096))]
+struct Page([usize; 512]);
+
+impl Page {
+    const ZERO: Self = Self([0; 512]);
+}
+
+static mut ROOT: Page = Page::ZERO;
+static mut MIDDLE: Page = Page::ZERO;
+static mut LEAF: Page = Page::ZERO;
+static mut FRAMES: [Page; PAGES] = [Page::ZERO, Page::ZERO, Page::ZERO, Page::ZERO];
+
+/// 按需分配物理页的页异常处理器。
+struct Lazy {
+    allocated: usize,
+}
+
+static mut HANDLER: Lazy = Lazy { allocated: 0 };
+
+impl PageFaultHandler for Lazy {
+    fn handle(&mut self, fault: &PageFault, _ctx: &mut FlowContext) -> PageFaultResult {
+        log::info!("page fault: {fault:x?}");
+        if fault.from_user || fault.access == AccessKind::Fetch {
+            return PageFaultResult::Kill;
+        }
+        let index = match fault.addr.checked_sub(LAZY) {
+            Some(offset) if offset < PAGES * 4096 => offset / 4096,
+            _ => return PageFaultResult::Kill,
+        };
+        let leaf = unsafe { &mut LEAF.0[index] };
+        if *leaf & V != 0 {
+            return PageFaultResult::Retry;
+        }
+        let frame = unsafe { &FRAMES[self.allocated] } as *const Page as usize;
+        self.allocated += 1;
+        *leaf = ppn(frame) | A | D | W | R | V;
+        PageFaultResult::Resolved
+    }
+
+    fn kill(&mut self, fault: &PageFault, _ctx: EntireContextSeparated) -> EntireResult {
+        panic!("unexpected page fault: {fault:x?}")
+    }
+}
+
+/// 物理地址 `addr` 所在页的页表项中的页号部分。
+#[inline]
+const fn ppn(addr: usize) -> usize {
+    addr >> 12 << 10
+}
+
+pub(crate) fn run() {
+    let mut context = FlowContext::ZERO;
+    let loaded = FreeTrapStack::new(
+        StackRef(unsafe { &mut FREE_STACK }),
+        NonNull::from(&mut context),
+        fast_handler,
+    )
+    .unwrap()
+    .load();
+
+    unsafe {
+        // 设备和内存以 1 GiB 大页恒等映射
+        ROOT.0[0] = ppn(0) | A | D | X | W | R | V;
+        ROOT.0[2] = ppn(0x8000_0000) | A | D | X | W | R | V;
+        ROOT.0[LAZY >> 30] = ppn(&MIDDLE as *const _ as _) | V;
+        MIDDLE.0[0] = ppn(&LEAF as *const _ as _) | V;
+        satp::set(satp::Mode::Sv39, 0, &ROOT as *const _ as usize >> 12);
+        riscv::asm::sfence_vma_all();
+    }
+
+    let pages = unsafe { core::slice::from_raw_parts_mut(LAZY as *mut usize, PAGES * 512) };
+    pages[0] = 0x5050;
+    assert_eq!(0, pages[512]);
+    assert_eq!(0x5050, pages[0]);
+    pages[3 * 512 + 1] = 1;
+    assert_eq!(3, unsafe { HANDLER.allocated });
+    assert_eq!(0x5050, unsafe { FRAMES[0].0[0] });
+    assert_eq!(1, unsafe { FRAMES[2].0[1] });
+
+    unsafe {
+        satp::set(satp::Mode::Bare, 0, 0);
+        riscv::asm::sfence_vma_all();
+    }
+    log::info!("lazy pages allocated on demand");
+    drop(loaded);
+}
+
+extern "C" fn fast_handler(
+    mut ctx: FastContext,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> FastResult {
+    ctx.save_args(a1, a2, a3, a4, a5, a6, a7);
+    match ctx.handle_page_fault(unsafe { &mut HANDLER }) {
+        Ok(result) => result,
+        Err(_) => unreachable!(),
+    }
+}
@@ -3,17 +3,44 @@
 #![feature(naked_functions, asm_const)]
 #![deny(warnings)]
 
+#[cfg(feature = "m-mode")]
+mod aia_uart;
+mod call_with;
+#[cfg(feature = "gdb-pty")]
+mod gdb_pty;
+#[cfg(feature = "gdbstub")]
+mod gdb_script;
+#[cfg(feature = "m-mode")]
+mod ipc_bench;
+#[cfg(all(feature = "s-mode", target_arch = "riscv64"))]
+mod lazy_paging;
+mod nested_trap;
+mod plic_uart;
+mod remote_ipi;
+mod signal_user;
+mod timer_wheel;
+mod uaccess_fault;
+
 use core::{
     arch::asm,
+    future::poll_fn,
     mem::{forget, MaybeUninit},
+    pin::Pin,
     ptr::{null, NonNull},
+    sync::atomic::AtomicU32,
+    task::Poll,
     unreachable,
 };
-use dtb_walker::{Dtb, DtbObj, HeaderError, Str, WalkOperation};
+use dtb_walker::{Dtb, DtbObj, HeaderError, Property, Str, WalkOperation};
 use fast_trap::{
-    load_direct_trap_entry, reuse_stack_for_trap, soft_trap, trap_entry, FastContext, FastResult,
-    FlowContext, FreeTrapStack, TrapStackBlock,
+    load_direct_trap_entry, preemptible, print_backtrace, reuse_stack_for_trap, soft_trap,
+    trap_entry, Backtrace, FastContext, FastResult, FlowContext, FreeTrapStack, Preempted,
+    Preemption, StackFrame, Threads, TrapStackBlock,
 };
+#[cfg(feature = "trace")]
+use fast_trap::{CauseStats, TraceBuffer, TrapStats};
+use fast_trap_executor::{Executor, Task};
+use fast_trap_sched::{Clock, FixedPriority, Scheduler};
 use rcore_console::log;
 use riscv::register::*;
 use sifive_test_device::SifiveTestDevice;
@@ -23,19 +50,36 @@ use uart_16550::MmioSerialPort;
 static mut ROOT_STACK: Stack = Stack([0; 4096]);
 static mut FREE_STACK: Stack = Stack([0; 4096]);
 static mut ROOT_CONTEXT: FlowContext = FlowContext::ZERO;
+#[cfg(feature = "trace")]
+static TRACE: TraceBuffer = TraceBuffer::new();
+#[cfg(feature = "trace")]
+static STATS: TrapStats = TrapStats::new();
+/// 还没有主硬件线程。
+///
+/// 最先进入 `_start` 的硬件线程把它清零，成为主硬件线程。初值非零，不会被清零 bss 段影响。
+static UNCLAIMED: AtomicU32 = AtomicU32::new(1);
+/// 主硬件线程的编号。
+static mut BOOT_HART: usize = 0;
 
 #[naked]
 #[no_mangle]
 #[link_section = ".text.entry"]
 unsafe extern "C" fn _start() -> ! {
     asm!(
-        "   la   sp, {stack} + {stack_size}
+        "   la        t0, {unclaimed}
+            amoswap.w t0, zero, (t0)
+            bnez      t0, 1f
+            tail      {secondary}
+         1: la   sp, {stack} + {stack_size}
+            mv   s0, zero
             call {move_stack}
             call {main}
             j    {trap}
         ",
         stack_size = const 4096,
         stack      =   sym ROOT_STACK,
+        unclaimed  =   sym UNCLAIMED,
+        secondary  =   sym remote_ipi::secondary_start,
         move_stack =   sym reuse_stack_for_trap,
         main       =   sym rust_main,
         trap       =   sym trap_entry,
@@ -48,13 +92,17 @@ unsafe extern "C" fn exception() -> ! {
     asm!("unimp", options(noreturn),)
 }
 
-extern "C" fn rust_main(_hartid: usize, dtb: *const u8) {
+#[cfg_attr(feature = "gdb-pty", allow(unreachable_code))]
+extern "C" fn rust_main(hartid: usize, dtb: *const u8) {
     // 清零 bss 段
     extern "C" {
         static mut sbss: u64;
         static mut ebss: u64;
     }
-    unsafe { r0::zero_bss(&mut sbss, &mut ebss) };
+    unsafe {
+        r0::zero_bss(&mut sbss, &mut ebss);
+        BOOT_HART = hartid;
+    }
     // 初始化打印
     unsafe {
         Dtb::from_raw_parts_filtered(dtb, |e| {
@@ -67,7 +115,7 @@ extern "C" fn rust_main(_hartid: usize, dtb: *const u8) {
     .unwrap()
     .walk(|path, obj| match obj {
         DtbObj::SubNode { name } => {
-            if path.is_root() && name == Str::from("soc") {
+            if path.is_root() && (name == Str::from("soc") || name == Str::from("cpus")) {
                 WalkOperation::StepInto
             } else if path.level() == 1 {
                 #[inline]
@@ -77,11 +125,33 @@ extern "C" fn rust_main(_hartid: usize, dtb: *const u8) {
 
                 if name.starts_with("test") {
                     unsafe { TEST = parse_address(&name.as_bytes()[5..]) as _ };
+                } else if name.starts_with("cpu@") {
+                    unsafe { HARTS += 1 };
+                    // 从指令集扩展中找到 Sstc
+                    return WalkOperation::StepInto;
+                } else if name.starts_with("clint") {
+                    unsafe {
+                        CLINT = parse_address(&name.as_bytes()[6..]);
+                        MSWI = CLINT;
+                        MTIMER = MSWI + 0x4000;
+                    }
+                } else if name.starts_with("mswi") {
+                    unsafe { MSWI = parse_address(&name.as_bytes()[5..]) };
+                } else if name.starts_with("mtimer") {
+                    unsafe { MTIMER = parse_address(&name.as_bytes()[7..]) };
+                } else if name.starts_with("imsics") {
+                    // M 态的中断文件地址较低
+                    let addr = unsafe { parse_address(&name.as_bytes()[7..]) };
+                    unsafe { IMSIC = if IMSIC == 0 { addr } else { IMSIC.min(addr) } };
+                } else if name.starts_with("aplic") {
+                    let addr = unsafe { parse_address(&name.as_bytes()[6..]) };
+                    unsafe { APLIC = if APLIC == 0 { addr } else { APLIC.min(addr) } };
+                } else if name.starts_with("plic") {
+                    unsafe { PLIC = parse_address(&name.as_bytes()[5..]) };
                 } else if name.starts_with("uart") {
                     unsafe {
-                        UART = MaybeUninit::new(MmioSerialPort::new(parse_address(
-                            &name.as_bytes()[5..],
-                        )))
+                        UART_BASE = parse_address(&name.as_bytes()[5..]);
+                        UART = MaybeUninit::new(MmioSerialPort::new(UART_BASE))
                     };
                 }
                 WalkOperation::StepOver
@@ -89,8 +159,21 @@ extern "C" fn rust_main(_hartid: usize, dtb: *const u8) {
                 WalkOperation::StepOver
             }
         }
+        DtbObj::Property(Property::General { name, value })
+            if path.name().starts_with("cpu@")
+                && (name == Str::from("riscv,isa")
+                    || name == Str::from("riscv,isa-extensions")) =>
+        {
+            if value.windows(4).any(|ext| ext == b"sstc") {
+                unsafe { SSTC = true };
+            }
+            WalkOperation::StepOver
+        }
         DtbObj::Property(_) => WalkOperation::StepOver,
     });
+    // UART 只留给调试器连接，不初始化打印
+    #[cfg(feature = "gdb-pty")]
+    gdb_pty::run();
     rcore_console::init_console(&Console);
     rcore_console::set_log_level(option_env!("LOG"));
     rcore_console::test_log();
@@ -137,16 +220,144 @@ extern "C" fn rust_main(_hartid: usize, dtb: *const u8) {
 
     {
         // 叠加一个陷入栈用于临时保护
-        let _loaded = FreeTrapStack::new(
+        #[cfg_attr(not(feature = "trace"), allow(unused_mut))]
+        let mut temporary = FreeTrapStack::new_painted(
             StackRef(unsafe { &mut FREE_STACK }),
             context_ptr,
             fast_handler,
         )
-        .unwrap()
-        .load();
+        .unwrap();
+        #[cfg(feature = "trace")]
+        {
+            temporary.set_trace(&TRACE);
+            temporary.set_stats(&STATS);
+        }
+        let temporary = temporary.load();
         // 模拟陷入
         unsafe { soft_trap(cause::CALL) };
+        assert!(unsafe { DEFERRED_RAN });
+        #[cfg(feature = "trace")]
+        check_trace();
+        // 报告陷入栈用量
+        log::info!(
+            "trap stack high water mark: {}",
+            temporary.high_water_mark()
+        );
+    }
+
+    // 测试抢占忙等循环
+    let mut timer = Timer { preempted: 0 };
+    preemptible(
+        StackRef(unsafe { &mut FREE_STACK }),
+        100_000,
+        &mut timer,
+        || {
+            while unsafe { (&PREEMPTED as *const usize).read_volatile() } < 3 {
+                core::hint::spin_loop();
+            }
+        },
+    )
+    .unwrap();
+    assert_eq!(3, timer.preempted);
+    log::info!("busy loop preempted {} times", timer.preempted);
+
+    // 测试抢占式执行器：第一个任务忙等第二个任务设置的标记
+    let mut spin = poll_fn(|_| {
+        while !unsafe { (&FLAG as *const bool).read_volatile() } {
+            core::hint::spin_loop();
+        }
+        Poll::Ready(())
+    });
+    let mut set = poll_fn(|_| {
+        unsafe { FLAG = true };
+        Poll::Ready(())
+    });
+    let (spin, set) = (Pin::new(&mut spin), Pin::new(&mut set));
+    let mut tasks = [Task::new(spin), Task::new(set)];
+    Executor::new(Timer { preempted: 0 }, &mut tasks, unsafe {
+        &mut WORKER_STACKS
+    })
+    .run(StackRef(unsafe { &mut FREE_STACK }), 100_000)
+    .unwrap();
+    log::info!("preemptive executor finished");
+
+    // 测试线程：计数线程主动让出，忙等线程只能被抢占
+    let threads = Threads::<_, 4>::new(Timer { preempted: 0 });
+    threads
+        .run(StackRef(unsafe { &mut FREE_STACK }), 100_000, || {
+            let [a, b, c] = unsafe { &mut THREAD_STACKS };
+            let spin = threads.spawn(a, spinner, 2).unwrap();
+            let one = threads.spawn(b, counter, 1).unwrap();
+            let two = threads.spawn(c, counter, 2).unwrap();
+            assert_eq!(10, threads.join(one));
+            assert_eq!(20, threads.join(two));
+            assert_eq!(2, threads.join(spin));
+        })
+        .unwrap();
+    log::info!("threads joined");
+
+    // 测试调度器：高优先级任务周期性睡眠，两个低优先级任务轮转
+    let tasks = [
+        fast_trap_sched::Task::new(0),
+        fast_trap_sched::Task::new(1),
+        fast_trap_sched::Task::new(1),
+    ];
+    let sched: Sched = Scheduler::new(Timer { preempted: 0 }, FixedPriority::new(), &tasks);
+    let [a, b, c] = unsafe { &mut THREAD_STACKS };
+    sched.spawn(0, a, sleeper, &sched as *const _ as _);
+    sched.spawn(1, b, worker, 1);
+    sched.spawn(2, c, worker, 2);
+    sched
+        .run(StackRef(unsafe { &mut FREE_STACK }), 100_000, unsafe {
+            &mut WORKER_STACKS[0]
+        })
+        .unwrap();
+    for (i, task) in tasks.iter().enumerate() {
+        assert_eq!(Some(i), task.exit_code());
+        log::info!("task {i}: runtime {}", task.runtime());
     }
+    log::info!("idle: {}", sched.idle_time());
+
+    // 测试软件定时器
+    timer_wheel::run();
+
+    // 测试外部中断分发
+    if unsafe { PLIC } != 0 {
+        plic_uart::run();
+    }
+    #[cfg(feature = "m-mode")]
+    if unsafe { IMSIC } != 0 {
+        aia_uart::run();
+    }
+
+    // 测试调用返回
+    call_with::run();
+
+    // 测试陷入嵌套和双重故障
+    nested_trap::run();
+
+    // 测试异常修复
+    uaccess_fault::run();
+
+    // 测试按需分页
+    #[cfg(all(feature = "s-mode", target_arch = "riscv64"))]
+    lazy_paging::run();
+
+    // 测试调试桩
+    #[cfg(feature = "gdbstub")]
+    gdb_script::run();
+
+    // 测试信号投递
+    signal_user::run();
+
+    // 测试跨硬件线程请求
+    if unsafe { HARTS } > 1 {
+        remote_ipi::run();
+    }
+
+    // 测试 IPC 往返
+    #[cfg(feature = "m-mode")]
+    ipc_bench::run();
 
     #[cfg(feature = "m-mode")]
     {
@@ -171,6 +382,13 @@ extern "C" fn rust_main(_hartid: usize, dtb: *const u8) {
 mod cause {
     pub(super) const BOOT: usize = 24;
     pub(super) const CALL: usize = 25;
+    #[cfg(feature = "m-mode")]
+    pub(super) const IPC: usize = 26;
+    pub(super) const USER: usize = 27;
+    pub(super) const CALL_WITH: usize = 28;
+    pub(super) const NEST: usize = 29;
+    pub(super) const DOUBLE_FAULT: usize = 30;
+    pub(super) const RESCUE: usize = 31;
 }
 
 extern "C" fn fast_handler(
@@ -196,7 +414,11 @@ extern "C" fn fast_handler(
             T::Exception(E::Unknown) => {
                 match cause.bits() {
                     cause::BOOT => mepc::write(exception as _),
-                    cause::CALL => log::warn!("call fast-trap inline!"),
+                    cause::CALL => {
+                        log::warn!("call fast-trap inline!");
+                        assert_trap_in_backtrace();
+                        ctx.defer(deferred_work, 1).unwrap();
+                    }
                     _ => unreachable!(),
                 }
                 unsafe { mstatus::set_mpp(mstatus::MPP::Machine) };
@@ -219,7 +441,11 @@ extern "C" fn fast_handler(
             T::Exception(E::Unknown) => {
                 match cause.bits() {
                     cause::BOOT => mepc::write(exception as _),
-                    cause::CALL => log::warn!("call fast-trap inline!"),
+                    cause::CALL => {
+                        log::warn!("call fast-trap inline!");
+                        assert_trap_in_backtrace();
+                        ctx.defer(deferred_work, 1).unwrap();
+                    }
                     _ => unreachable!(),
                 }
                 unsafe { sstatus::set_spp(sstatus::SPP::Supervisor) };
@@ -231,9 +457,167 @@ extern "C" fn fast_handler(
     }
 }
 
+/// 检查临时陷入栈上记录的陷入事件和统计。
+#[cfg(feature = "trace")]
+fn check_trace() {
+    // 导出陷入事件，有延迟工作的快速路径应该转到完整路径
+    let mut continued = false;
+    for event in TRACE.drain() {
+        log::info!(
+            "trap event: cause = {:#x}, epc = {:#x}, {:?} in {} cycles",
+            event.cause,
+            event.epc,
+            event.result,
+            event.cycles(),
+        );
+        continued |= event.cause == cause::CALL && event.result == FastResult::Continue;
+    }
+    assert!(continued);
+    assert_eq!(0, TRACE.dropped());
+    // 报告陷入统计
+    for (cause, stats) in STATS.causes() {
+        log::info!(
+            "trap cause {cause:#x}: {} fast, {} continued",
+            stats.fast(),
+            stats.continued
+        );
+    }
+    log::info!("fast cycles (log2): {:?}", STATS.fast_histogram());
+    log::info!("continued cycles (log2): {:?}", STATS.continued_histogram());
+    assert_eq!(
+        CauseStats {
+            total: 1,
+            continued: 1
+        },
+        STATS.cause(cause::CALL)
+    );
+    assert_eq!(1, STATS.continued_histogram().iter().sum::<usize>());
+}
+
+/// 回溯应该跨过陷入，回到模拟陷入的根控制流。
+fn assert_trap_in_backtrace() {
+    let mut frames = Backtrace::capture();
+    assert!(frames.any(|frame| matches!(frame, StackFrame::Trap { level: 1, .. })));
+    let interrupted = frames.count();
+    assert!(interrupted > 0);
+    log::info!("backtrace crossed the trap into {interrupted} frames");
+}
+
+static mut DEFERRED_RAN: bool = false;
+
+/// 延迟工作，在从完整路径恢复前开中断执行。
+fn deferred_work(arg: usize) {
+    #[cfg(feature = "m-mode")]
+    assert!(mstatus::read().mie());
+    #[cfg(feature = "s-mode")]
+    assert!(sstatus::read().sie());
+    log::info!("deferred work {arg}");
+    unsafe { DEFERRED_RAN = true };
+}
+
+static mut PREEMPTED: usize = 0;
+static mut FLAG: bool = false;
+static mut WORKER_STACKS: [[u8; 4096]; 2] = [[0; 4096]; 2];
+static mut THREAD_STACKS: [[u8; 4096]; 3] = [[0; 4096]; 3];
+static mut COUNTED: usize = 0;
+
+extern "C" fn counter(id: usize) -> usize {
+    for i in 0..3 {
+        log::info!("thread {id}: {i}");
+        fast_trap::yield_now();
+    }
+    unsafe { COUNTED += 1 };
+    id * 10
+}
+
+extern "C" fn spinner(n: usize) -> usize {
+    while unsafe { (&COUNTED as *const usize).read_volatile() } < n {
+        core::hint::spin_loop();
+    }
+    log::info!("spinner done");
+    n
+}
+
+type Sched<'a> = Scheduler<'a, Timer, FixedPriority<2, 2>>;
+
+extern "C" fn sleeper(sched: usize) -> usize {
+    let sched = unsafe { &*(sched as *const Sched) };
+    for i in 0..3 {
+        log::info!("high priority task: {i}");
+        sched.sleep(300_000);
+    }
+    0
+}
+
+extern "C" fn worker(id: usize) -> usize {
+    let mut count = 0usize;
+    for _ in 0..1_000_000 {
+        count = unsafe { (&count as *const usize).read_volatile() } + 1;
+    }
+    log::info!("worker {id} counted {count}");
+    id
+}
+
+/// 用于测试抢占的定时器。
+struct Timer {
+    preempted: usize,
+}
+
+impl Preemption for Timer {
+    fn arm(&mut self, budget: u64) {
+        set_timer(time() + budget);
+    }
+
+    fn disarm(&mut self) {
+        set_timer(u64::MAX);
+    }
+
+    fn preempted(&mut self, preempted: Preempted) -> Preempted {
+        self.preempted += 1;
+        unsafe { PREEMPTED = self.preempted };
+        log::debug!("preempted at {:#x}", preempted.context().pc);
+        preempted
+    }
+}
+
+impl Clock for Timer {
+    #[inline]
+    fn now(&self) -> u64 {
+        time()
+    }
+}
+
+#[cfg(feature = "m-mode")]
+fn time() -> u64 {
+    unsafe { ((MTIMER + 0x7ff8) as *const u64).read_volatile() }
+}
+
+#[cfg(feature = "m-mode")]
+fn set_timer(time: u64) {
+    unsafe { ((MTIMER + 8 * BOOT_HART) as *mut u64).write_volatile(time) };
+}
+
+#[cfg(feature = "s-mode")]
+fn time() -> u64 {
+    time::read64()
+}
+
+#[cfg(feature = "s-mode")]
+fn set_timer(time: u64) {
+    #[cfg(target_pointer_width = "64")]
+    unsafe {
+        asm!("ecall", in("a7") 0x54494d45, in("a6") 0, inlateout("a0") time => _, lateout("a1") _)
+    };
+    #[cfg(target_pointer_width = "32")]
+    unsafe {
+        asm!("ecall", in("a7") 0x54494d45, in("a6") 0, inlateout("a0") time as usize => _, inlateout("a1") (time >> 32) as usize => _)
+    };
+}
+
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     log::error!("{info}");
+    print_backtrace();
     unsafe { &*TEST }.fail(-1 as _)
 }
 
@@ -267,6 +651,17 @@ impl Drop for StackRef {
 struct Console;
 static mut UART: MaybeUninit<MmioSerialPort> = MaybeUninit::uninit();
 static mut TEST: *const SifiveTestDevice = null();
+static mut HARTS: usize = 0;
+/// CLINT 的基地址，使用 ACLINT 时为 0。
+static mut CLINT: usize = 0;
+/// 硬件线程是否支持 Sstc 扩展。
+static mut SSTC: bool = false;
+static mut MSWI: usize = 0;
+static mut MTIMER: usize = 0;
+static mut PLIC: usize = 0;
+static mut IMSIC: usize = 0;
+static mut APLIC: usize = 0;
+static mut UART_BASE: usize = 0;
 
 impl rcore_console::Console for Console {
     #[inline]
@@ -0,0 +1,219 @@
+//! 陷入嵌套和双重故障测试。
+//!
+//! 快速路径中执行 `ebreak` 引起第 2 级陷入，它加入一项延迟工作并从完整路径返回。
+//! 然后完整路径把 sp 移到陷入栈底再执行 `ebreak`，剩余的栈空间不足以压入嵌套的上下文，
+//! 双重故障处理函数检查应急栈上的转储，再通过陷入回到根控制流。
+
+use crate::{cause, Stack, StackRef, FREE_STACK};
+use core::{arch::asm, ptr::NonNull};
+use fast_trap::{
+    soft_trap, DoubleFault, EntireContext, EntireResult, FastContext, FastResult, FlowContext,
+    FreeTrapStack,
+};
+use rcore_console::log;
+use riscv::register::*;
+
+/// `ebreak` 的陷入原因。
+const BREAKPOINT: usize = 3;
+/// 故障现场 t2 的值。
+const MARKER: usize = 0x505;
+
+static mut ROOT: FlowContext = FlowContext::ZERO;
+/// 双重故障前保存的根控制流。
+static mut SAVED: FlowContext = FlowContext::ZERO;
+static mut EMERGENCY: Stack = Stack([0; 4096]);
+/// 陷入处理器上下文的地址。
+static mut HANDLER: usize = 0;
+/// 第 2 级陷入看到的嵌套级别。
+static mut NESTED: usize = 0;
+static mut DEFERRED: usize = 0;
+static mut FAULTED: bool = false;
+
+pub(crate) fn run() {
+    let mut stack = FreeTrapStack::new(
+        StackRef(unsafe { &mut FREE_STACK }),
+        NonNull::from(unsafe { &mut ROOT }),
+        fast_handler,
+    )
+    .unwrap();
+    stack.set_double_fault(unsafe { &mut EMERGENCY.0 }, double_fault);
+    let loaded = stack.load();
+    unsafe { HANDLER = read_scratch() };
+
+    unsafe { soft_trap(cause::NEST) };
+    assert_eq!(2, unsafe { NESTED });
+    assert_eq!(2, unsafe { DEFERRED });
+    log::info!("nested trap handled at level 2");
+
+    unsafe { soft_trap(cause::DOUBLE_FAULT) };
+    assert!(unsafe { FAULTED });
+    assert_eq!(unsafe { HANDLER }, read_scratch());
+    log::info!("double fault handled on the emergency stack");
+    drop(loaded);
+}
+
+extern "C" fn fast_handler(
+    mut ctx: FastContext,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> FastResult {
+    ctx.save_args(a1, a2, a3, a4, a5, a6, a7);
+    match (ctx.nesting_level(), read_cause()) {
+        (1, cause::NEST) => {
+            // 嵌套的陷入会覆盖 epc
+            let epc = read_epc();
+            unsafe {
+                asm!(
+                    "   .option push
+                        .option norvc
+                        ebreak
+                        .option pop
+                    "
+                )
+            };
+            write_epc(epc);
+            set_previous_privilege_to_kernel();
+            ctx.restore()
+        }
+        (2, BREAKPOINT) => {
+            unsafe { NESTED = ctx.nesting_level() };
+            write_epc(read_epc() + 4);
+            let mut ctx = ctx.call_with(never_completes).unwrap_err();
+            // 第 1 级陷入没有在执行延迟工作，由第 2 级陷入转到完整路径执行
+            ctx.defer(deferred_work, 2).unwrap();
+            ctx.restore()
+        }
+        (1, cause::DOUBLE_FAULT) => ctx.continue_with(overflow_stack, ()),
+        (1, cause::RESCUE) => {
+            set_previous_privilege_to_kernel();
+            ctx.switch_to(NonNull::from(unsafe { &mut SAVED }))
+        }
+        (level, code) => unreachable!("trap {code:#x} at level {level}"),
+    }
+}
+
+extern "C" fn never_completes(
+    _ctx: FastContext,
+    _a1: usize,
+    _a2: usize,
+    _a3: usize,
+    _a4: usize,
+    _a5: usize,
+    _a6: usize,
+    _a7: usize,
+) -> FastResult {
+    unreachable!()
+}
+
+fn deferred_work(arg: usize) {
+    unsafe { DEFERRED = arg };
+}
+
+/// 保存完整的根控制流，然后在陷入处理中耗尽陷入栈。
+extern "C" fn overflow_stack(ctx: EntireContext) -> EntireResult {
+    let (mut ctx, _) = ctx.split();
+    let root = ctx.regs();
+    root.pc = read_epc();
+    unsafe {
+        asm!("mv {}, gp", "mv {}, tp", out(reg) root.gp, out(reg) root.tp);
+        SAVED = core::ptr::read(root);
+        overflow(FREE_STACK.0.as_ptr() as usize + 64)
+    }
+}
+
+/// 把 sp 设置为 `sp`，t2 设置为 [`MARKER`]，然后执行 `ebreak`。
+#[naked]
+unsafe extern "C" fn overflow(sp: usize) -> ! {
+    asm!(
+        "   .option push
+            .option norvc
+            mv   sp, a0
+            li   t2, {marker}
+            ebreak
+            .option pop
+        ",
+        marker = const MARKER,
+        options(noreturn),
+    )
+}
+
+/// 检查故障现场，然后令突发寄存器重新指向陷入处理器上下文，通过陷入回到根控制流。
+extern "C" fn double_fault(fault: &DoubleFault) -> ! {
+    let bottom = unsafe { FREE_STACK.0.as_ptr() as usize };
+    assert_eq!(BREAKPOINT, fault.cause);
+    assert_eq!(overflow as usize + 8, fault.context.pc);
+    assert_eq!(bottom + 64, fault.context.sp);
+    assert_eq!(MARKER, fault.context.t[2]);
+    assert_eq!(bottom..unsafe { HANDLER }, fault.stack());
+    unsafe {
+        FAULTED = true;
+        write_scratch(HANDLER);
+        soft_trap(cause::RESCUE);
+    }
+    unreachable!()
+}
+
+#[cfg(feature = "m-mode")]
+fn read_cause() -> usize {
+    mcause::read().bits()
+}
+
+#[cfg(feature = "m-mode")]
+fn read_epc() -> usize {
+    mepc::read()
+}
+
+#[cfg(feature = "m-mode")]
+fn write_epc(pc: usize) {
+    mepc::write(pc)
+}
+
+#[cfg(feature = "m-mode")]
+fn read_scratch() -> usize {
+    mscratch::read()
+}
+
+#[cfg(feature = "m-mode")]
+fn write_scratch(val: usize) {
+    mscratch::write(val)
+}
+
+#[cfg(feature = "m-mode")]
+fn set_previous_privilege_to_kernel() {
+    unsafe { mstatus::set_mpp(mstatus::MPP::Machine) };
+}
+
+#[cfg(feature = "s-mode")]
+fn read_cause() -> usize {
+    scause::read().bits()
+}
+
+#[cfg(feature = "s-mode")]
+fn read_epc() -> usize {
+    sepc::read()
+}
+
+#[cfg(feature = "s-mode")]
+fn write_epc(pc: usize) {
+    sepc::write(pc)
+}
+
+#[cfg(feature = "s-mode")]
+fn read_scratch() -> usize {
+    sscratch::read()
+}
+
+#[cfg(feature = "s-mode")]
+fn write_scratch(val: usize) {
+    sscratch::write(val)
+}
+
+#[cfg(feature = "s-mode")]
+fn set_previous_privilege_to_kernel() {
+    unsafe { sstatus::set_spp(sstatus::SPP::Supervisor) };
+}
@@ -0,0 +1,157 @@
+//! 外部中断分发测试。
+//!
+//! 打开 UART 的发送保持寄存器空中断，它会立即触发，处理函数关闭它。
+//! 先用快速处理函数，再换成完整路径的处理函数各测一次，
+//! 最后不用分发器，由 `IrqWakers::wake_and_restore` 唤醒注册的 `Waker`。
+
+use crate::{StackRef, BOOT_HART, FREE_STACK, PLIC, UART_BASE};
+use core::{
+    ptr::{null, NonNull},
+    task::{RawWaker, RawWakerVTable, Waker},
+};
+use fast_trap::{
+    FastContext, FastResult, FlowContext, FreeTrapStack, IrqDispatcher, IrqHandler, IrqWakers, Plic,
+};
+use rcore_console::log;
+use riscv::register::*;
+
+/// qemu `virt` 上 UART 的中断源编号。
+pub(crate) const UART_IRQ: usize = 10;
+/// UART 中断使能寄存器的偏移。
+pub(crate) const IER: usize = 1;
+/// 发送保持寄存器空中断。
+pub(crate) const ETBEI: u8 = 1 << 1;
+
+/// 启动硬件线程在当前特权级的 PLIC 上下文。
+#[cfg(feature = "m-mode")]
+fn plic_context() -> usize {
+    2 * unsafe { BOOT_HART }
+}
+
+/// 启动硬件线程在当前特权级的 PLIC 上下文。
+#[cfg(feature = "s-mode")]
+fn plic_context() -> usize {
+    2 * unsafe { BOOT_HART } + 1
+}
+
+static mut IRQS: Option<IrqDispatcher<Plic, 32>> = None;
+static WAKERS: IrqWakers<32> = IrqWakers::new();
+static mut HANDLED: usize = 0;
+
+pub(crate) fn run() {
+    let mut context = FlowContext::ZERO;
+    let loaded = FreeTrapStack::new(
+        StackRef(unsafe { &mut FREE_STACK }),
+        NonNull::from(&mut context),
+        fast_handler,
+    )
+    .unwrap()
+    .load();
+
+    let plic = unsafe { Plic::new(PLIC, plic_context()) };
+    plic.set_priority(UART_IRQ, 1);
+    plic.set_threshold(0);
+    plic.enable(UART_IRQ);
+    let irqs = unsafe { IRQS.insert(IrqDispatcher::new(plic)) };
+
+    irqs.register(UART_IRQ, IrqHandler::Fast(uart_fast));
+    trigger(1);
+    let irqs = unsafe { IRQS.as_mut() }.unwrap();
+    irqs.register(UART_IRQ, IrqHandler::Entire(uart_entire));
+    trigger(2);
+
+    // 分发器取走后，快速路径函数唤醒注册的 `Waker`
+    let mut irqs = unsafe { IRQS.take() }.unwrap();
+    let waker = unsafe { Waker::from_raw(RawWaker::new(null(), &VTABLE)) };
+    assert!(WAKERS.register(UART_IRQ, &waker));
+    assert!(!WAKERS.register(32, &waker));
+    trigger(3);
+    assert!(!WAKERS.wake(UART_IRQ));
+
+    irqs.controller().disable(UART_IRQ);
+    log::info!("uart interrupt dispatched");
+    drop(loaded);
+}
+
+/// 唤醒时关闭 UART 中断并计数。
+static VTABLE: RawWakerVTable = RawWakerVTable::new(
+    |_| RawWaker::new(null(), &VTABLE),
+    |_| uart_fast(UART_IRQ),
+    |_| uart_fast(UART_IRQ),
+    |_| {},
+);
+
+/// 打开发送保持寄存器空中断，等待第 `n` 次处理完成。
+fn trigger(n: usize) {
+    unsafe {
+        ((UART_BASE + IER) as *mut u8).write_volatile(ETBEI);
+        enable_external();
+        while (&HANDLED as *const usize).read_volatile() != n {
+            riscv::asm::wfi();
+        }
+        disable_external();
+    }
+}
+
+fn uart_fast(_irq: usize) {
+    unsafe {
+        ((UART_BASE + IER) as *mut u8).write_volatile(0);
+        HANDLED += 1;
+    }
+}
+
+fn uart_entire(irq: usize) {
+    log::info!("uart interrupt {irq} in entire path");
+    uart_fast(irq);
+}
+
+extern "C" fn fast_handler(
+    mut ctx: FastContext,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> FastResult {
+    ctx.save_args(a1, a2, a3, a4, a5, a6, a7);
+    #[cfg(feature = "m-mode")]
+    assert!(matches!(
+        mcause::read().cause(),
+        mcause::Trap::Interrupt(mcause::Interrupt::MachineExternal)
+    ));
+    #[cfg(feature = "s-mode")]
+    assert!(matches!(
+        scause::read().cause(),
+        scause::Trap::Interrupt(scause::Interrupt::SupervisorExternal)
+    ));
+    match unsafe { IRQS.as_mut() } {
+        Some(irqs) => irqs.dispatch(ctx),
+        None => WAKERS.wake_and_restore(ctx, &mut unsafe { Plic::new(PLIC, plic_context()) }),
+    }
+}
+
+#[cfg(feature = "m-mode")]
+unsafe fn enable_external() {
+    mie::set_mext();
+    mstatus::set_mie();
+}
+
+#[cfg(feature = "m-mode")]
+unsafe fn disable_external() {
+    mstatus::clear_mie();
+    mie::clear_mext();
+}
+
+#[cfg(feature = "s-mode")]
+unsafe fn enable_external() {
+    sie::set_sext();
+    sstatus::set_sie();
+}
+
+#[cfg(feature = "s-mode")]
+unsafe fn disable_external() {
+    sstatus::clear_sie();
+    sie::clear_sext();
+}
@@ -0,0 +1,202 @@
+//! 跨硬件线程请求测试。
+//!
+//! 需要 `-smp 2`：主硬件线程依次向另一个硬件线程发送调用、重新调度和停止请求，
+//! 后者在快速路径中处理它们。
+
+use crate::{Stack, StackRef};
+use core::{
+    arch::asm,
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+};
+use fast_trap::{
+    load_direct_trap_entry, FastContext, FastResult, FlowContext, FreeTrapStack, Mailbox, Request,
+};
+use rcore_console::log;
+use riscv::register::*;
+
+/// 参与测试的硬件线程的启动栈不能被主硬件线程清零。
+#[link_section = ".bss.uninit"]
+static mut SECONDARY_STACK: Stack = Stack([0; 4096]);
+static mut SECONDARY_TRAP_STACK: Stack = Stack([0; 4096]);
+static mut SECONDARY_CONTEXT: FlowContext = FlowContext::ZERO;
+
+/// 还没有硬件线程参与测试。
+///
+/// 主硬件线程以外最先进入的硬件线程把它清零，参与测试。初值非零，不会被清零 bss 段影响。
+static UNCLAIMED: AtomicU32 = AtomicU32::new(1);
+/// 参与测试的硬件线程的编号，同样不能在 bss 段。
+static PARTNER: AtomicUsize = AtomicUsize::new(usize::MAX);
+static MAILBOX: Mailbox = Mailbox::new();
+/// 主硬件线程完成初始化后放行参与测试的硬件线程。
+static GO: AtomicBool = AtomicBool::new(false);
+static PONG: AtomicUsize = AtomicUsize::new(0);
+static RESCHEDULED: AtomicUsize = AtomicUsize::new(0);
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// 主硬件线程以外的硬件线程从这里进入，只有最先进入的参与测试，其余的停住。
+#[naked]
+pub(crate) unsafe extern "C" fn secondary_start() -> ! {
+    asm!(
+        "   la        t0, {unclaimed}
+            amoswap.w t0, zero, (t0)
+            beqz      t0, 1f
+            la   sp, {stack} + {stack_size}
+            call {main}
+         1: wfi
+            j    1b
+        ",
+        stack_size = const 4096,
+        stack      =   sym SECONDARY_STACK,
+        unclaimed  =   sym UNCLAIMED,
+        main       =   sym secondary_main,
+        options(noreturn),
+    )
+}
+
+extern "C" fn secondary_main(hartid: usize) {
+    PARTNER.store(hartid, Ordering::Release);
+    while !GO.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+    let loaded = FreeTrapStack::new(
+        StackRef(unsafe { &mut SECONDARY_TRAP_STACK }),
+        NonNull::from(unsafe { &mut SECONDARY_CONTEXT }),
+        fast_handler,
+    )
+    .unwrap()
+    .load();
+    unsafe {
+        load_direct_trap_entry();
+        enable_soft();
+    }
+    while !STOPPED.load(Ordering::Acquire) {
+        unsafe { riscv::asm::wfi() };
+    }
+    unsafe { disable_soft() };
+    drop(loaded);
+}
+
+pub(crate) fn run() {
+    GO.store(true, Ordering::Release);
+    // S 模式下只有主硬件线程被启动，选一个其他的硬件线程参与测试
+    #[cfg(feature = "s-mode")]
+    hart_start(if unsafe { crate::BOOT_HART } == 0 {
+        1
+    } else {
+        0
+    });
+    let partner = loop {
+        match PARTNER.load(Ordering::Acquire) {
+            usize::MAX => core::hint::spin_loop(),
+            hart => break hart,
+        }
+    };
+
+    send(Request::Run(pong, 7), partner);
+    while PONG.load(Ordering::Acquire) != 7 {
+        core::hint::spin_loop();
+    }
+    send(Request::Reschedule, partner);
+    while RESCHEDULED.load(Ordering::Acquire) != 1 {
+        core::hint::spin_loop();
+    }
+    send(Request::Stop, partner);
+    while !STOPPED.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+    log::info!("remote requests handled by hart {partner}");
+}
+
+/// 向参与测试的硬件线程 `hart` 发送请求，邮箱满时等待。
+fn send(mut request: Request, hart: usize) {
+    while let Err(r) = MAILBOX.send(request, &ipi(), hart) {
+        request = r;
+        core::hint::spin_loop();
+    }
+}
+
+fn pong(arg: usize) {
+    PONG.store(arg, Ordering::Release);
+}
+
+extern "C" fn fast_handler(
+    mut ctx: FastContext,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> FastResult {
+    ctx.save_args(a1, a2, a3, a4, a5, a6, a7);
+    match MAILBOX.receive(&ipi(), PARTNER.load(Ordering::Relaxed)) {
+        Some(Request::Run(f, arg)) => f(arg),
+        Some(Request::Reschedule) => {
+            RESCHEDULED.fetch_add(1, Ordering::AcqRel);
+        }
+        Some(Request::Stop) => STOPPED.store(true, Ordering::Release),
+        None => {}
+    }
+    ctx.restore()
+}
+
+#[cfg(feature = "m-mode")]
+fn ipi() -> fast_trap::Mswi {
+    unsafe { fast_trap::Mswi::new(crate::MSWI) }
+}
+
+#[cfg(feature = "m-mode")]
+unsafe fn enable_soft() {
+    mie::set_msoft();
+    mstatus::set_mie();
+}
+
+#[cfg(feature = "m-mode")]
+unsafe fn disable_soft() {
+    mstatus::clear_mie();
+    mie::clear_msoft();
+}
+
+/// 通过 SBI 发送的核间中断。
+#[cfg(feature = "s-mode")]
+struct SbiIpi;
+
+#[cfg(feature = "s-mode")]
+impl fast_trap::Ipi for SbiIpi {
+    fn send(&self, hart: usize) {
+        unsafe {
+            asm!("ecall", in("a7") 0x735049, in("a6") 0, inlateout("a0") 1 << hart => _, inlateout("a1") 0 => _)
+        };
+    }
+
+    fn clear(&self, _hart: usize) {
+        unsafe { asm!("csrci sip, {}", const 1 << 1) };
+    }
+}
+
+#[cfg(feature = "s-mode")]
+fn ipi() -> SbiIpi {
+    SbiIpi
+}
+
+/// 通过 SBI HSM 启动硬件线程。
+#[cfg(feature = "s-mode")]
+fn hart_start(hartid: usize) {
+    unsafe {
+        asm!("ecall", in("a7") 0x48534d, in("a6") 0, inlateout("a0") hartid => _, inlateout("a1") crate::_start as usize => _, in("a2") 0)
+    };
+}
+
+#[cfg(feature = "s-mode")]
+unsafe fn enable_soft() {
+    sie::set_ssoft();
+    sstatus::set_sie();
+}
+
+#[cfg(feature = "s-mode")]
+unsafe fn disable_soft() {
+    sstatus::clear_sie();
+    sie::clear_ssoft();
+}
@@ -0,0 +1,201 @@
+//! 信号投递测试。
+//!
+//! 用户程序请求向自己发送信号，内核在完整路径中把信号帧压入用户栈并转到信号处理函数，
+//! 处理函数破坏一些寄存器后通过 `sigreturn` 返回，用户程序检查寄存器是否恢复。
+
+use crate::{cause, StackRef, FREE_STACK, THREAD_STACKS};
+use core::{arch::asm, ptr::NonNull};
+use fast_trap::{
+    fast_fixup_exception, soft_trap, EntireContext, EntireResult, FastContext, FastResult,
+    FlowContext, FreeTrapStack,
+};
+use rcore_console::log;
+use riscv::register::*;
+
+/// 请求向自己发送信号。
+const RAISE: usize = 0;
+/// 从信号处理函数返回。
+const SIGRETURN: usize = 1;
+/// 结束测试，回到根控制流。
+const EXIT: usize = 2;
+/// 测试用的信号编号。
+const SIGNO: usize = 10;
+
+static mut ROOT: FlowContext = FlowContext::ZERO;
+static mut USER: FlowContext = FlowContext::ZERO;
+static mut RECEIVED: usize = 0;
+
+pub(crate) fn run() {
+    let [_, _, stack] = unsafe { &mut THREAD_STACKS };
+    unsafe {
+        USER.sp = stack.as_mut_ptr_range().end as usize & !15;
+        USER.pc = user_main as usize;
+        asm!("mv {}, gp", "mv {}, tp", out(reg) USER.gp, out(reg) USER.tp);
+    }
+    #[cfg(feature = "m-mode")]
+    unsafe {
+        // 允许 U 态访问所有地址
+        asm!("csrw pmpaddr0, {}", "csrw pmpcfg0, {}", in(reg) usize::MAX, in(reg) 0x1f);
+    }
+    let loaded = FreeTrapStack::new(
+        StackRef(unsafe { &mut FREE_STACK }),
+        NonNull::from(unsafe { &mut ROOT }),
+        fast_handler,
+    )
+    .unwrap()
+    .load();
+    unsafe { soft_trap(cause::USER) };
+    assert_eq!(SIGNO, unsafe { RECEIVED });
+    log::info!("signal {SIGNO} delivered to user mode");
+    drop(loaded);
+}
+
+/// 用户程序：t2、a0 和 s2 在信号处理前后应该保持不变。
+#[naked]
+unsafe extern "C" fn user_main() -> ! {
+    asm!(
+        "   li   t2, 0x5050
+            li   a0, 0x1234
+            li   s2, 0x77
+            li   a7, {raise}
+            ecall
+            mv   a1, t2
+            mv   a2, a0
+            mv   a3, s2
+            li   a7, {exit}
+            ecall
+         1: j    1b
+        ",
+        raise = const RAISE,
+        exit  = const EXIT,
+        options(noreturn),
+    )
+}
+
+/// 信号处理函数：a0 指向信号帧，附加信息紧跟在上下文之后，记录信号编号后返回。
+#[naked]
+unsafe extern "C" fn user_handler() -> ! {
+    asm!(
+        "   li   t2, 0
+            li   s2, 0
+            addi a0, a0, {info}
+            lw   t0, 0(a0)
+            la   t1, {received}
+            sw   t0, 0(t1)
+            li   a7, {sigreturn}
+            ecall
+         1: j    1b
+        ",
+        info      = const core::mem::size_of::<FlowContext>(),
+        received  = sym RECEIVED,
+        sigreturn = const SIGRETURN,
+        options(noreturn),
+    )
+}
+
+extern "C" fn fast_handler(
+    mut ctx: FastContext,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> FastResult {
+    ctx.save_args(a1, a2, a3, a4, a5, a6, a7);
+    if read_cause() == cause::USER {
+        return ctx.continue_with(enter, ());
+    }
+    let ctx = match fast_fixup_exception(ctx) {
+        Ok(result) => return result,
+        Err(ctx) => ctx,
+    };
+    assert_eq!(8, read_cause(), "unexpected trap from user mode");
+    let user = NonNull::from(unsafe { &mut USER });
+    let regs = unsafe { &mut USER };
+    match a7 {
+        // 快速路径没有保存 s 寄存器，转到完整路径投递
+        RAISE => ctx.continue_with(raise, ()),
+        SIGRETURN => {
+            assert_eq!(SIGNO as u32, unsafe { regs.sigreturn::<u32>() }.unwrap());
+            ctx.switch_to(user)
+        }
+        EXIT => {
+            assert_eq!((0x5050, 0x1234, 0x77), (a1, a2, a3));
+            set_previous_privilege_to_kernel();
+            ctx.switch_to(NonNull::from(unsafe { &mut ROOT }))
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// 向用户程序投递信号，此时上下文中的 s 寄存器已经保存。
+extern "C" fn raise(ctx: EntireContext) -> EntireResult {
+    let (mut ctx, _) = ctx.split();
+    let regs = ctx.regs();
+    regs.pc = read_epc() + 4;
+    unsafe { regs.deliver_signal(user_handler as usize, SIGNO as u32) }.unwrap();
+    write_epc(regs.pc);
+    ctx.restore()
+}
+
+/// 保存根控制流，进入用户程序。
+extern "C" fn enter(ctx: EntireContext) -> EntireResult {
+    let (mut ctx, _) = ctx.split();
+    let root = ctx.regs();
+    root.pc = read_epc();
+    unsafe { asm!("mv {}, gp", "mv {}, tp", out(reg) root.gp, out(reg) root.tp) };
+    ctx.swap_context(NonNull::from(unsafe { &mut USER }));
+    #[cfg(feature = "m-mode")]
+    unsafe {
+        mepc::write(USER.pc);
+        mstatus::set_mpp(mstatus::MPP::User);
+    }
+    #[cfg(feature = "s-mode")]
+    unsafe {
+        sepc::write(USER.pc);
+        sstatus::set_spp(sstatus::SPP::User);
+    }
+    ctx.restore()
+}
+
+#[cfg(feature = "m-mode")]
+fn read_cause() -> usize {
+    mcause::read().bits()
+}
+
+#[cfg(feature = "m-mode")]
+fn read_epc() -> usize {
+    mepc::read()
+}
+
+#[cfg(feature = "m-mode")]
+fn write_epc(pc: usize) {
+    mepc::write(pc)
+}
+
+#[cfg(feature = "m-mode")]
+fn set_previous_privilege_to_kernel() {
+    unsafe { mstatus::set_mpp(mstatus::MPP::Machine) };
+}
+
+#[cfg(feature = "s-mode")]
+fn read_cause() -> usize {
+    scause::read().bits()
+}
+
+#[cfg(feature = "s-mode")]
+fn read_epc() -> usize {
+    sepc::read()
+}
+
+#[cfg(feature = "s-mode")]
+fn write_epc(pc: usize) {
+    sepc::write(pc)
+}
+
+#[cfg(feature = "s-mode")]
+fn set_previous_privilege_to_kernel() {
+    unsafe { sstatus::set_spp(sstatus::SPP::Supervisor) };
+}
@@ -0,0 +1,179 @@
+//! 软件定时器测试。
+//!
+//! 快速回调和非快速回调各一个，还有一个要从高层时间轮重新放置的定时器和一个被取消的定时器。
+//! M 态按平台使用 CLINT 或 ACLINT 的定时器，S 态在支持 Sstc 扩展时直接写 `stimecmp`，否则通过 SBI。
+
+use crate::{StackRef, FREE_STACK};
+use core::ptr::NonNull;
+use fast_trap::{FastContext, FastResult, FlowContext, FreeTrapStack, TimerEntry, TimerWheel};
+use rcore_console::log;
+use riscv::register::*;
+
+#[cfg(feature = "m-mode")]
+type Device = fast_trap::MmioTimer;
+
+static mut WHEEL: Option<TimerWheel<Device, 3>> = None;
+static mut FAST: TimerEntry = TimerEntry::fast(fire, 1);
+static mut DEFERRED: TimerEntry = TimerEntry::deferred(report, 2);
+static mut FAR: TimerEntry = TimerEntry::deferred(report, 4);
+static mut CANCELED: TimerEntry = TimerEntry::fast(fire, 8);
+/// 已执行的回调参数之和。
+static mut FIRED: usize = 0;
+
+pub(crate) fn run() {
+    use fast_trap::TimerDevice;
+
+    let mut context = FlowContext::ZERO;
+    let loaded = FreeTrapStack::new(
+        StackRef(unsafe { &mut FREE_STACK }),
+        NonNull::from(&mut context),
+        fast_handler,
+    )
+    .unwrap()
+    .load();
+
+    // 以 1024 个计时周期为一拍，最远的定时器在第 2 层
+    let wheel = unsafe { WHEEL.insert(TimerWheel::new(device(), 10)) };
+    let now = wheel.device().now();
+    unsafe {
+        wheel.add(NonNull::from(&mut FAST), now + 100_000);
+        wheel.add(NonNull::from(&mut DEFERRED), now + 50_000);
+        wheel.add(NonNull::from(&mut FAR), now + 5_000_000);
+        wheel.add(NonNull::from(&mut CANCELED), now + 200_000);
+        assert!(wheel.cancel(NonNull::from(&mut CANCELED)));
+    }
+
+    unsafe { enable_timer() };
+    while unsafe { (&FIRED as *const usize).read_volatile() } != 7 {
+        unsafe { riscv::asm::wfi() };
+    }
+    unsafe { disable_timer() };
+
+    let mut wheel = unsafe { WHEEL.take() }.unwrap();
+    assert!(!unsafe { CANCELED.is_armed() });
+    log::info!(
+        "timer wheel: far timer fired {} cycles late",
+        wheel.device().now() - unsafe { FAR.deadline() }
+    );
+    drop(loaded);
+}
+
+fn fire(arg: usize) {
+    unsafe { FIRED += arg };
+}
+
+fn report(arg: usize) {
+    log::info!("deferred timer {arg} fired");
+    fire(arg);
+}
+
+extern "C" fn fast_handler(
+    mut ctx: FastContext,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> FastResult {
+    ctx.save_args(a1, a2, a3, a4, a5, a6, a7);
+    #[cfg(feature = "m-mode")]
+    assert!(matches!(
+        mcause::read().cause(),
+        mcause::Trap::Interrupt(mcause::Interrupt::MachineTimer)
+    ));
+    #[cfg(feature = "s-mode")]
+    assert!(matches!(
+        scause::read().cause(),
+        scause::Trap::Interrupt(scause::Interrupt::SupervisorTimer)
+    ));
+    unsafe { WHEEL.as_mut() }.unwrap().handle(ctx)
+}
+
+#[cfg(feature = "m-mode")]
+fn device() -> Device {
+    unsafe {
+        if crate::CLINT != 0 {
+            fast_trap::MmioTimer::clint(crate::CLINT, crate::BOOT_HART)
+        } else {
+            fast_trap::MmioTimer::aclint(crate::MTIMER, crate::BOOT_HART)
+        }
+    }
+}
+
+#[cfg(feature = "m-mode")]
+unsafe fn enable_timer() {
+    mie::set_mtimer();
+    mstatus::set_mie();
+}
+
+#[cfg(feature = "m-mode")]
+unsafe fn disable_timer() {
+    mstatus::clear_mie();
+    mie::clear_mtimer();
+}
+
+/// 通过 SBI 设置的定时器。
+#[cfg(feature = "s-mode")]
+pub(crate) struct SbiTimer;
+
+#[cfg(feature = "s-mode")]
+impl fast_trap::TimerDevice for SbiTimer {
+    #[inline]
+    fn now(&self) -> u64 {
+        crate::time()
+    }
+
+    #[inline]
+    fn set_deadline(&mut self, deadline: u64) {
+        crate::set_timer(deadline)
+    }
+}
+
+/// S 态的定时器。
+#[cfg(feature = "s-mode")]
+enum Device {
+    Sbi(SbiTimer),
+    Sstc(fast_trap::Sstc),
+}
+
+#[cfg(feature = "s-mode")]
+impl fast_trap::TimerDevice for Device {
+    #[inline]
+    fn now(&self) -> u64 {
+        match self {
+            Self::Sbi(timer) => timer.now(),
+            Self::Sstc(timer) => timer.now(),
+        }
+    }
+
+    #[inline]
+    fn set_deadline(&mut self, deadline: u64) {
+        match self {
+            Self::Sbi(timer) => timer.set_deadline(deadline),
+            Self::Sstc(timer) => timer.set_deadline(deadline),
+        }
+    }
+}
+
+#[cfg(feature = "s-mode")]
+fn device() -> Device {
+    if unsafe { crate::SSTC } {
+        Device::Sstc(fast_trap::Sstc)
+    } else {
+        Device::Sbi(SbiTimer)
+    }
+}
+
+#[cfg(feature = "s-mode")]
+unsafe fn enable_timer() {
+    sie::set_stimer();
+    sstatus::set_sie();
+}
+
+#[cfg(feature = "s-mode")]
+unsafe fn disable_timer() {
+    sstatus::clear_sie();
+    sie::clear_stimer();
+}
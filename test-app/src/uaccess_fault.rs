@@ -0,0 +1,63 @@
+//! 异常修复测试。
+//!
+//! 从不存在的地址复制会引起访问异常，快速路径查修复表跳出复制循环，复制函数返回错误。
+
+use crate::{StackRef, FREE_STACK};
+use core::ptr::NonNull;
+use fast_trap::{
+    copy_from_user, copy_to_user, fast_fixup_exception, FastContext, FastResult, FlowContext,
+    FreeTrapStack, UserFault,
+};
+use rcore_console::log;
+
+/// qemu `virt` 上没有映射任何设备的地址。
+const UNMAPPED: usize = 0x80_0000;
+
+static mut FIXED: usize = 0;
+
+pub(crate) fn run() {
+    let mut context = FlowContext::ZERO;
+    let loaded = FreeTrapStack::new(
+        StackRef(unsafe { &mut FREE_STACK }),
+        NonNull::from(&mut context),
+        fast_handler,
+    )
+    .unwrap()
+    .load();
+
+    let src = *b"fast-trap";
+    let mut dst = [0u8; 9];
+    assert!(unsafe { copy_from_user(&mut dst, src.as_ptr()) }.is_ok());
+    assert_eq!(src, dst);
+    assert!(matches!(
+        unsafe { copy_from_user(&mut dst, UNMAPPED as _) },
+        Err(UserFault)
+    ));
+    assert!(matches!(
+        unsafe { copy_to_user(UNMAPPED as _, &src) },
+        Err(UserFault)
+    ));
+    assert_eq!(2, unsafe { FIXED });
+    log::info!("user access faults fixed up");
+    drop(loaded);
+}
+
+extern "C" fn fast_handler(
+    mut ctx: FastContext,
+    a1: usize,
+    a2: usize,
+    a3: usize,
+    a4: usize,
+    a5: usize,
+    a6: usize,
+    a7: usize,
+) -> FastResult {
+    ctx.save_args(a1, a2, a3, a4, a5, a6, a7);
+    match fast_fixup_exception(ctx) {
+        Ok(result) => {
+            unsafe { FIXED += 1 };
+            result
+        }
+        Err(_) => unreachable!(),
+    }
+}
@@ -6,7 +6,11 @@ use once_cell::sync::Lazy;
 use os_xtask_utils::{BinUtil, Cargo, CommandExt, Qemu};
 use std::{
     fs,
+    io::{BufRead, BufReader},
     path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
 };
 
 static PROJECT: Lazy<&'static Path> =
@@ -23,8 +27,11 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     Make(BuildArgs),
+    MakeAll(BuildArgs),
     Asm(AsmArgs),
     Qemu(QemuArgs),
+    Gdb(GdbArgs),
+    GdbStub(GdbStubArgs),
 }
 
 fn main() {
@@ -33,8 +40,11 @@ fn main() {
         Make(args) => {
             args.make();
         }
+        MakeAll(args) => args.make_all(),
         Asm(args) => args.dump(),
         Qemu(args) => args.run(),
+        Gdb(args) => args.test(),
+        GdbStub(args) => args.test(),
     }
 }
 
@@ -73,6 +83,12 @@ struct BuildArgs {
     /// build in debug mode
     #[clap(long)]
     debug: bool,
+    /// build without the gdbstub and trace features
+    #[clap(long)]
+    plain: bool,
+    /// Extra features of test-app, set by subcommands.
+    #[clap(skip)]
+    features: Vec<&'static str>,
 }
 
 impl BuildArgs {
@@ -84,9 +100,14 @@ impl BuildArgs {
             Arch::RISCV64(Mode::Machine) => ("riscv64imac-unknown-none-elf", ["m-mode"]),
             Arch::RISCV64(Mode::Supervisor) => ("riscv64imac-unknown-none-elf", ["s-mode"]),
         };
+        let extra: &[&str] = if self.plain {
+            &[]
+        } else {
+            &["gdbstub", "trace"]
+        };
         Cargo::build()
             .package(package)
-            .features(true, feature)
+            .features(true, feature.iter().chain(extra).chain(&self.features))
             .optional(&self.log, |cargo, log| {
                 cargo.env("LOG", log);
             })
@@ -101,6 +122,14 @@ impl BuildArgs {
             .join(if self.debug { "debug" } else { "release" })
             .join(package)
     }
+
+    /// Builds test-app both with and without the gdbstub and trace features.
+    fn make_all(mut self) {
+        for plain in [false, true] {
+            self.plain = plain;
+            self.make();
+        }
+    }
 }
 
 #[derive(Args)]
@@ -128,27 +157,265 @@ struct QemuArgs {
     /// Port for gdb to connect. If set, qemu will block and wait gdb to connect.
     #[clap(long)]
     gdb: Option<u16>,
+    /// Use ACLINT instead of CLINT.
+    #[clap(long)]
+    aclint: bool,
+    /// Use AIA (APLIC and IMSIC) instead of PLIC.
+    #[clap(long)]
+    aia: bool,
+    /// Number of harts.
+    #[clap(long)]
+    smp: Option<u8>,
 }
 
 impl QemuArgs {
     fn run(self) {
         let elf = self.build.make();
+        self.command(elf, &["-serial", "mon:stdio"]).invoke();
+    }
+
+    fn command(&self, elf: PathBuf, serial: &[&str]) -> Qemu {
         let (arch, mode) = match self.build.arch {
             Arch::RISCV32(Mode::Machine) => ("riscv32", "-bios"),
             Arch::RISCV32(Mode::Supervisor) => ("riscv32", "-kernel"),
             Arch::RISCV64(Mode::Machine) => ("riscv64", "-bios"),
             Arch::RISCV64(Mode::Supervisor) => ("riscv64", "-kernel"),
         };
-        Qemu::system(arch)
-            .args(&["-machine", "virt"])
+        let mut machine = String::from("virt");
+        if self.aclint {
+            machine.push_str(",aclint=on");
+        }
+        if self.aia {
+            machine.push_str(",aia=aplic-imsic");
+        }
+        let mut qemu = Qemu::system(arch);
+        qemu.args(&["-machine", machine.as_str()])
             .arg("-nographic")
             .arg(mode)
             .arg(objcopy(elf, true))
-            .args(&["-serial", "mon:stdio"])
+            .args(serial)
+            .optional(&self.smp, |qemu, smp| {
+                qemu.args(["-smp", &smp.to_string()]);
+            })
             .optional(&self.gdb, |qemu, gdb| {
                 qemu.args(["-S", "-gdb", &format!("tcp::{gdb}")]);
+            });
+        qemu
+    }
+}
+
+#[derive(Args)]
+struct GdbArgs {
+    #[clap(flatten)]
+    qemu: QemuArgs,
+    /// The gdb executable.
+    #[clap(long, default_value = "gdb-multiarch")]
+    gdb_exec: String,
+}
+
+impl GdbArgs {
+    /// Stops in trap handlers and checks that gdb unwinds through the trap routines.
+    ///
+    /// Covers the first fast handler, a nested trap whose deferred work runs in the entire path,
+    /// an entire-path handler and a double fault handler on the emergency stack.
+    fn test(mut self) {
+        let port = *self.qemu.gdb.get_or_insert(1234);
+        self.qemu.build.debug = true;
+        let elf = self.qemu.build.make();
+        let script = elf.with_extension("gdb");
+        fs::write(
+            &script,
+            format!(
+                "\
+set pagination off
+set confirm off
+target remote localhost:{port}
+break test_app::fast_handler
+break test_app::nested_trap::deferred_work
+break test_app::nested_trap::overflow_stack
+break test_app::nested_trap::double_fault
+continue
+echo ==fast\\n
+backtrace
+delete 1
+continue
+echo ==nested\\n
+backtrace
+continue
+echo ==entire\\n
+backtrace
+continue
+echo ==double-fault\\n
+backtrace
+kill
+"
+            ),
+        )
+        .unwrap();
+        let mut qemu = self
+            .qemu
+            .command(elf.clone(), &["-serial", "mon:stdio"])
+            .as_mut()
+            .spawn()
+            .unwrap();
+        // gdb retries the connection until qemu listens on the port
+        let output = Command::new(&self.gdb_exec)
+            .args(["-batch", "-nx", "-x"])
+            .arg(&script)
+            .arg(&elf)
+            .output()
+            .unwrap();
+        let _ = qemu.kill();
+        qemu.wait().unwrap();
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        println!("{stdout}");
+        // each backtrace must contain these frames in order, from the innermost
+        let expected: [(&str, &[&str]); 4] = [
+            ("fast", &["fast_handler", "fast_trap_entry", "rust_main"]),
+            (
+                "nested",
+                &[
+                    "nested_trap::deferred_work",
+                    "fast_trap_exit",
+                    "nested_trap::fast_handler",
+                    "fast_trap_entry",
+                    "nested_trap::run",
+                    "rust_main",
+                ],
+            ),
+            (
+                "entire",
+                &[
+                    "nested_trap::overflow_stack",
+                    "fast_trap_exit",
+                    "nested_trap::run",
+                    "rust_main",
+                ],
+            ),
+            (
+                "double-fault",
+                &[
+                    "nested_trap::double_fault",
+                    "fast_trap_entry",
+                    "nested_trap::overflow",
+                ],
+            ),
+        ];
+        let sections = stdout.split("==").skip(1).collect::<Vec<_>>();
+        assert_eq!(
+            expected.len(),
+            sections.len(),
+            "gdb stops {} times",
+            sections.len()
+        );
+        for ((name, names), section) in expected.into_iter().zip(sections) {
+            assert!(section.starts_with(name), "unexpected stop {section}");
+            let mut frames = section.lines().filter(|line| line.starts_with('#'));
+            for expected in names {
+                assert!(
+                    frames.any(|frame| frame.contains(expected)),
+                    "{name} backtrace does not reach {expected}"
+                );
+            }
+            println!("{name} backtrace unwinds through the trap routines");
+        }
+    }
+}
+
+#[derive(Args)]
+struct GdbStubArgs {
+    #[clap(flatten)]
+    qemu: QemuArgs,
+    /// The gdb executable.
+    #[clap(long, default_value = "gdb-multiarch")]
+    gdb_exec: String,
+}
+
+impl GdbStubArgs {
+    /// Connects gdb to the stub in test-app through the serial pty, steps and continues the target.
+    fn test(mut self) {
+        self.qemu.gdb = None;
+        self.qemu.build.debug = true;
+        self.qemu.build.features.push("gdb-pty");
+        let elf = self.qemu.build.make();
+        let mut qemu = self
+            .qemu
+            .command(elf.clone(), &["-serial", "pty", "-monitor", "none"])
+            .as_mut()
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // qemu reports the pty as "char device redirected to /dev/pts/N (label serial0)"
+        let mut lines = BufReader::new(qemu.stderr.take().unwrap())
+            .lines()
+            .map(Result::unwrap);
+        let pty = lines
+            .find_map(|line| {
+                line.split_whitespace()
+                    .find(|word| word.starts_with("/dev/"))
+                    .map(str::to_string)
             })
-            .invoke();
+            .expect("qemu does not redirect the serial to a pty");
+        // keeps the pipe open for the rest of qemu's messages
+        thread::spawn(move || lines.for_each(|line| eprintln!("{line}")));
+        let script = elf.with_extension("stub.gdb");
+        fs::write(
+            &script,
+            format!(
+                "\
+set pagination off
+set confirm off
+target remote {pty}
+set $pc = $pc + 4
+stepi
+stepi
+print $a0
+tbreak *(gdb_pty_target + 16)
+continue
+print $a0
+detach
+"
+            ),
+        )
+        .unwrap();
+        let output = Command::new(&self.gdb_exec)
+            .args(["-batch", "-nx", "-x"])
+            .arg(&script)
+            .arg(&elf)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        println!("{stdout}");
+
+        // test-app shuts qemu down after the target returns
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let status = loop {
+            if let Some(status) = qemu.try_wait().unwrap() {
+                break Some(status);
+            }
+            if Instant::now() > deadline {
+                let _ = qemu.kill();
+                qemu.wait().unwrap();
+                break None;
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+        let values = stdout
+            .lines()
+            .filter(|line| line.starts_with('$'))
+            .filter_map(|line| line.split_once("= ").map(|(_, value)| value.trim()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            ["6", "7"],
+            "gdb does not step the target through the stub"
+        );
+        match status {
+            Some(status) if status.success() => println!("gdb debugs the target through the stub"),
+            Some(status) => panic!("test-app fails after detaching: {status}"),
+            None => panic!("test-app does not exit after detaching"),
+        }
     }
 }
 